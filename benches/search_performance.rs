@@ -174,5 +174,25 @@ fn pattern_complexity_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, search_benchmark, memory_benchmark, pattern_complexity_benchmark);
+fn parallel_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parallel_search");
+    group.sample_size(10);
+
+    // Big enough to cross the parallel threshold many times over
+    let data = generate_log_data(2_000_000);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    for (name, threshold) in [("sequential", usize::MAX), ("parallel", 0)] {
+        group.bench_with_input(BenchmarkId::new(name, data.len()), &data, |b, data| {
+            let engine = SearchEngine::new(data.len(), true, false)
+                .unwrap()
+                .with_parallel_search(threshold, 1024 * 1024);
+            b.iter(|| black_box(engine.search_bytes(black_box(data.as_bytes()), black_box("ERROR"))))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, search_benchmark, memory_benchmark, pattern_complexity_benchmark, parallel_benchmark);
 criterion_main!(benches);
\ No newline at end of file
@@ -0,0 +1,46 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Settings read from `~/.config/kitty-fast-search/config.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Remote-control password matching `remote_control_password` in kitty.conf
+    pub password: Option<String>,
+    /// File containing the remote-control password
+    pub password_file: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("kitty-fast-search").join("config.toml"))
+    }
+
+    /// Loads the user config, or defaults when there is no config file.
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) if path.exists() => Self::load_from(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// The configured password, reading `password_file` if that is used instead.
+    pub fn password(&self) -> Result<Option<String>> {
+        if self.password.is_some() {
+            return Ok(self.password.clone());
+        }
+        match &self.password_file {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)?;
+                Ok(Some(contents.trim_end_matches(['\r', '\n']).to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::Aes256Gcm;
+use anyhow::{Result, anyhow};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::kitty::protocol::RC_PROTOCOL_VERSION;
+
+/// Version prefix of `KITTY_PUBLIC_KEY` that we know how to use.
+pub const ENCRYPTION_PROTOCOL_VERSION: &str = "1";
+
+/// Environment variable holding the remote-control password, as used by `kitty @`.
+pub const PASSWORD_ENV: &str = "KITTY_RC_PASSWORD";
+
+const TAG_LEN: usize = 16;
+
+/// Picks the remote-control password from, in order: `--password`,
+/// `--password-file`, `KITTY_RC_PASSWORD`, then the config file.
+pub fn resolve_password(
+    cli_password: Option<String>,
+    cli_password_file: Option<&Path>,
+    config_password: Option<String>,
+) -> Result<Option<String>> {
+    if cli_password.is_some() {
+        return Ok(cli_password);
+    }
+    if let Some(path) = cli_password_file {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read password file {}: {}", path.display(), e))?;
+        return Ok(Some(contents.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        return Ok(Some(password));
+    }
+    Ok(config_password)
+}
+
+/// Encrypts remote-control messages for kitty's `remote_control_password`
+/// support: an X25519 key exchange with kitty's public key, followed by
+/// AES-256-GCM over the JSON command with the password and a timestamp added.
+pub struct CommandEncrypter {
+    secret: [u8; 32],
+    public_key: [u8; 32],
+    password: String,
+}
+
+impl CommandEncrypter {
+    /// `kitty_public_key` is in the `KITTY_PUBLIC_KEY` format: `1:<base85 key>`.
+    pub fn new(kitty_public_key: &str, password: &str) -> Result<Self> {
+        let (version, encoded) = kitty_public_key
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Malformed KITTY_PUBLIC_KEY"))?;
+        if version != ENCRYPTION_PROTOCOL_VERSION {
+            return Err(anyhow!(
+                "Unsupported kitty encryption protocol version {}, upgrade kitty-fast-search",
+                version
+            ));
+        }
+
+        let key: [u8; 32] = decode_base85(encoded)?
+            .try_into()
+            .map_err(|_| anyhow!("KITTY_PUBLIC_KEY has the wrong length"))?;
+
+        let ours = EphemeralSecret::random();
+        let public_key = PublicKey::from(&ours).to_bytes();
+        let shared = ours.diffie_hellman(&PublicKey::from(key));
+
+        Ok(Self {
+            secret: Sha256::digest(shared.as_bytes()).into(),
+            public_key,
+            password: password.to_string(),
+        })
+    }
+
+    pub fn from_env(password: &str) -> Result<Self> {
+        let key = std::env::var("KITTY_PUBLIC_KEY").map_err(|_| {
+            anyhow!("Password usage requires the KITTY_PUBLIC_KEY environment variable set by kitty")
+        })?;
+        Self::new(&key, password)
+    }
+
+    /// Wraps a plain remote-control message in kitty's encrypted envelope.
+    pub fn encrypt(&self, mut message: Value) -> Result<Value> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+        message["timestamp"] = json!(timestamp);
+        message["password"] = json!(self.password);
+
+        let cipher = Aes256Gcm::new_from_slice(&self.secret)
+            .map_err(|_| anyhow!("Invalid remote-control encryption key"))?;
+        let iv = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut encrypted = cipher
+            .encrypt(&iv, serde_json::to_vec(&message)?.as_slice())
+            .map_err(|_| anyhow!("Failed to encrypt remote-control command"))?;
+        let tag = encrypted.split_off(encrypted.len() - TAG_LEN);
+
+        Ok(json!({
+            "version": RC_PROTOCOL_VERSION,
+            "iv": encode_base85(&iv),
+            "tag": encode_base85(&tag),
+            "pubkey": encode_base85(&self.public_key),
+            "encrypted": encode_base85(&encrypted),
+            "enc_proto": ENCRYPTION_PROTOCOL_VERSION,
+        }))
+    }
+}
+
+const BASE85_ALPHABET: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Base85 as produced by Python's `base64.b85encode`, which kitty uses.
+fn encode_base85(data: &[u8]) -> String {
+    let padding = (4 - data.len() % 4) % 4;
+    let mut out = Vec::with_capacity((data.len() + padding) / 4 * 5);
+
+    for chunk in data.chunks(4) {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        let mut value = u32::from_be_bytes(word);
+        let mut digits = [0u8; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE85_ALPHABET[(value % 85) as usize];
+            value /= 85;
+        }
+        out.extend_from_slice(&digits);
+    }
+
+    out.truncate(out.len() - padding);
+    String::from_utf8(out).expect("base85 alphabet is ASCII")
+}
+
+fn decode_base85(text: &str) -> Result<Vec<u8>> {
+    let padding = (5 - text.len() % 5) % 5;
+    let mut digits: Vec<u8> = text.bytes().collect();
+    digits.extend(std::iter::repeat_n(b'~', padding));
+
+    let mut out = Vec::with_capacity(digits.len() / 5 * 4);
+    for chunk in digits.chunks(5) {
+        let mut value: u64 = 0;
+        for &c in chunk {
+            let digit = BASE85_ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or_else(|| anyhow!("Invalid base85 character {:?}", c as char))?;
+            value = value * 85 + digit as u64;
+        }
+        let value = u32::try_from(value).map_err(|_| anyhow!("Invalid base85 data"))?;
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    out.truncate(out.len() - padding);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::Nonce;
+
+    #[test]
+    fn test_base85_matches_python() {
+        assert_eq!(encode_base85(b"hello"), "Xk~0{Zv");
+        assert_eq!(encode_base85(b"a"), "VE");
+        assert_eq!(encode_base85(&[0, 0, 0, 0]), "00000");
+        assert_eq!(decode_base85("Xk~0{Zv").unwrap(), b"hello");
+        assert_eq!(decode_base85("VE").unwrap(), b"a");
+
+        let bytes: Vec<u8> = (0..32).collect();
+        assert_eq!(decode_base85(&encode_base85(&bytes)).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_rejects_unknown_key_version() {
+        assert!(CommandEncrypter::new("2:abc", "secret").is_err());
+        assert!(CommandEncrypter::new("no-version", "secret").is_err());
+    }
+
+    #[test]
+    fn test_kitty_can_decrypt_command() {
+        // Play kitty's side of the exchange
+        let kitty_secret = EphemeralSecret::random();
+        let kitty_public = PublicKey::from(&kitty_secret);
+        let env_key = format!("1:{}", encode_base85(kitty_public.as_bytes()));
+
+        let encrypter = CommandEncrypter::new(&env_key, "hunter2").unwrap();
+        let envelope = encrypter
+            .encrypt(json!({"cmd": "get-text", "payload": {}}))
+            .unwrap();
+        assert_eq!(envelope["enc_proto"], "1");
+
+        let field = |name: &str| decode_base85(envelope[name].as_str().unwrap()).unwrap();
+        let client_public: [u8; 32] = field("pubkey").try_into().unwrap();
+        let shared = kitty_secret.diffie_hellman(&PublicKey::from(client_public));
+        let key: [u8; 32] = Sha256::digest(shared.as_bytes()).into();
+
+        let mut sealed = field("encrypted");
+        sealed.extend(field("tag"));
+        let cipher = Aes256Gcm::new_from_slice(&key).unwrap();
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&field("iv")), sealed.as_slice())
+            .unwrap();
+
+        let message: Value = serde_json::from_slice(&plaintext).unwrap();
+        assert_eq!(message["cmd"], "get-text");
+        assert_eq!(message["password"], "hunter2");
+        assert!(message["timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_cli_password_takes_precedence() {
+        let password = resolve_password(
+            Some("from-cli".to_string()),
+            None,
+            Some("from-config".to_string()),
+        )
+        .unwrap();
+        assert_eq!(password.as_deref(), Some("from-cli"));
+    }
+
+    #[test]
+    fn test_password_file_is_trimmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password");
+        std::fs::write(&path, "s3cret\n").unwrap();
+
+        let password = resolve_password(None, Some(&path), None).unwrap();
+        assert_eq!(password.as_deref(), Some("s3cret"));
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::kitty::commands::Extent;
+use crate::kitty::transport::{self, Endpoint};
+
+/// A kitty version, e.g. `[0, 35, 2]`.
+pub type Version = [u32; 3];
+
+/// The oldest kitty the search works with, missing features aside.
+pub const MIN_KITTY_VERSION: Version = [0, 14, 0];
+
+/// What the kitty we talk to can do, derived from its version.
+///
+/// Features a kitty lacks are left out of the session rather than failing
+/// halfway through it. When the version is unknown every feature is assumed
+/// to be there, and kitty's own errors are reported as usual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub version: Option<Version>,
+    /// `get-text --ansi`
+    pub ansi_text: bool,
+    /// `get-text --add-wrap-markers`, for mapping lines to screen rows
+    pub wrap_markers: bool,
+    /// `create-marker` and `remove-marker`, with several highlight groups
+    pub markers: bool,
+    /// `scroll-window` to the start or end of the scrollback
+    pub scroll_window: bool,
+    /// `--extent=last_cmd_output` (needs shell integration too)
+    pub last_cmd_output: bool,
+    /// `--extent=last_visited_cmd_output`
+    pub last_visited_cmd_output: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::for_version(None)
+    }
+}
+
+impl Capabilities {
+    /// Capabilities of kitty `version`, or of a recent kitty when `None`.
+    pub fn for_version(version: Option<Version>) -> Self {
+        let since = |added: Version| version.is_none_or(|version| version >= added);
+        Self {
+            version,
+            ansi_text: since(MIN_KITTY_VERSION),
+            wrap_markers: since([0, 20, 0]),
+            markers: since([0, 15, 0]),
+            scroll_window: since([0, 15, 0]),
+            last_cmd_output: since([0, 22, 0]),
+            last_visited_cmd_output: since([0, 23, 0]),
+        }
+    }
+
+    /// Capabilities of the kitty reached through `endpoint`.
+    ///
+    /// Over the terminal kitty tells its own version. Over a socket the kitty
+    /// on `PATH` is asked, which is normally the one running, but not
+    /// necessarily the one at an address given with `--to` (`to`), so then
+    /// nothing is assumed.
+    pub async fn detect(endpoint: &Endpoint, to: Option<&str>, timeout: Duration) -> Self {
+        let version = match endpoint {
+            Endpoint::Tty => terminal_version(timeout).await,
+            _ if to.is_some() => None,
+            _ => kitty_version().await.as_deref().and_then(parse_version),
+        };
+        Self::for_version(version)
+    }
+
+    /// Whether this kitty is at least `MIN_KITTY_VERSION`, or unknown.
+    pub fn is_supported(&self) -> bool {
+        self.version.is_none_or(|version| version >= MIN_KITTY_VERSION)
+    }
+
+    pub fn supports_extent(&self, extent: Extent) -> bool {
+        match extent {
+            Extent::LastCmdOutput => self.last_cmd_output,
+            Extent::LastVisitedCmdOutput => self.last_visited_cmd_output,
+            Extent::Screen | Extent::All | Extent::Selection => true,
+        }
+    }
+
+    /// The extent after `extent` that this kitty can capture.
+    pub fn next_extent(&self, extent: Extent) -> Extent {
+        let mut next = extent.next();
+        while !self.supports_extent(next) {
+            next = next.next();
+        }
+        next
+    }
+}
+
+/// Shows the version as kitty does, e.g. `0.35.2`, or `unknown`.
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            Some([major, minor, patch]) => write!(f, "{}.{}.{}", major, minor, patch),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Output of `kitty --version`, when kitty is on `PATH`.
+pub async fn kitty_version() -> Option<String> {
+    tokio::process::Command::new("kitty")
+        .arg("--version")
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Asks the terminal for its name and version (XTVERSION). Every terminal
+/// answers the device attributes query sent after it, so one that ignores
+/// XTVERSION does not hold us up until the timeout.
+async fn terminal_version(timeout: Duration) -> Option<Version> {
+    match transport::query_terminal(b"\x1b[>q\x1b[c", timeout, has_device_attributes).await {
+        Ok(reply) => parse_xtversion(&reply),
+        Err(e) => {
+            debug!("Could not ask the terminal for its version: {}", e);
+            None
+        }
+    }
+}
+
+/// Whether `reply` ends with the answer to the device attributes query,
+/// e.g. `ESC [ ? 62 ; c`.
+fn has_device_attributes(reply: &[u8]) -> bool {
+    reply
+        .windows(3)
+        .position(|window| window == b"\x1b[?")
+        .is_some_and(|start| reply[start..].contains(&b'c'))
+}
+
+/// Extracts kitty's version from an XTVERSION reply, e.g.
+/// `ESC P >| kitty(0.35.2) ESC \`.
+fn parse_xtversion(reply: &[u8]) -> Option<Version> {
+    let reply = String::from_utf8_lossy(reply);
+    let start = reply.find("kitty(")? + "kitty(".len();
+    let end = start + reply[start..].find(')')?;
+    parse_version(&format!("kitty {}", &reply[start..end]))
+}
+
+/// Extracts the version from `kitty --version` output, e.g.
+/// `kitty 0.35.2 created by Kovid Goyal`.
+pub fn parse_version(output: &str) -> Option<Version> {
+    let version = output.split_whitespace().nth(1)?;
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+    Some([parts.next()??, parts.next()??, parts.next().flatten().unwrap_or(0)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("kitty 0.35.2 created by Kovid Goyal"), Some([0, 35, 2]));
+        assert_eq!(parse_version("kitty 0.26 created by Kovid Goyal"), Some([0, 26, 0]));
+        assert_eq!(parse_version("kitty"), None);
+    }
+
+    #[test]
+    fn test_parse_terminal_version() {
+        let reply = b"\x1bP>|kitty(0.35.2)\x1b\\\x1b[?62;c";
+        assert!(has_device_attributes(reply));
+        assert_eq!(parse_xtversion(reply), Some([0, 35, 2]));
+
+        // A terminal without XTVERSION only answers the second query
+        assert!(!has_device_attributes(b"\x1bP>|kitty(0.35.2)\x1b\\"));
+        assert!(has_device_attributes(b"\x1b[?1;2c"));
+        assert_eq!(parse_xtversion(b"\x1b[?1;2c"), None);
+    }
+
+    #[test]
+    fn test_old_kitty_lacks_newer_features() {
+        let old = Capabilities::for_version(Some([0, 21, 2]));
+        assert!(old.markers && old.ansi_text && old.wrap_markers);
+        assert!(!old.supports_extent(Extent::LastCmdOutput));
+        assert_eq!(old.next_extent(Extent::Screen), Extent::Selection);
+        assert_eq!(old.to_string(), "0.21.2");
+        assert!(old.is_supported());
+        assert!(!Capabilities::for_version(Some([0, 13, 4])).is_supported());
+
+        let unknown = Capabilities::default();
+        assert!(unknown.supports_extent(Extent::LastVisitedCmdOutput));
+        assert_eq!(unknown.next_extent(Extent::Screen), Extent::LastCmdOutput);
+    }
+}
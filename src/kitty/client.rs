@@ -1,128 +1,358 @@
 use anyhow::{Result, anyhow};
+use futures::future::join_all;
 use serde_json::Value;
-use std::process::Command;
-use tokio::process::Command as AsyncCommand;
+use std::io::IsTerminal;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, debug_span, Instrument};
 
+use crate::kitty::auth::CommandEncrypter;
+use crate::kitty::capabilities::Capabilities;
+use crate::kitty::commands::{Extent, KittyCommand, LaunchType, ScrollAmount};
+use crate::kitty::discovery;
+use crate::kitty::error::KittyError;
+use crate::kitty::marker::MarkerSpec;
+use crate::kitty::response::Response;
+use crate::kitty::scroll::{JumpPosition, JumpTarget, ScrollbackMap};
+use crate::kitty::transport::{Endpoint, KittyTransport, NativeTransport};
+use crate::kitty::windows::WindowTree;
+use crate::search::engine::WindowText;
+
+/// How long to wait for kitty to answer a command before giving up.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often reads that can safely be repeated are tried before giving up.
+const READ_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+#[derive(Clone)]
 pub struct KittyClient {
-    socket_path: Option<String>,
+    transport: Arc<dyn KittyTransport>,
+    capabilities: Capabilities,
+    timeout: Duration,
+    instance: Option<String>,
 }
 
 impl KittyClient {
-    pub async fn new() -> Result<Self> {
-        // Try to detect kitty socket
-        let socket_path = Self::detect_kitty_socket().await?;
-        Ok(Self { socket_path })
-    }
+    /// Connects to kitty, either through the socket at `to` (as accepted by
+    /// `kitty @ --to`), a discovered socket, or the controlling terminal.
+    /// With a `password`, commands are sent in kitty's encrypted envelope.
+    /// Commands kitty does not answer within `timeout` fail.
+    pub async fn new(to: Option<&str>, password: Option<&str>, timeout: Duration) -> Result<Self> {
+        let endpoint = match Self::detect_kitty_socket(to).await? {
+            Some(socket) => Endpoint::parse(&socket)?,
+            None => Endpoint::Tty,
+        };
 
-    async fn detect_kitty_socket() -> Result<Option<String>> {
-        // Check if we're running inside kitty
-        if std::env::var("KITTY_WINDOW_ID").is_ok() {
-            // Use default socket path
-            Ok(None)
-        } else {
-            Err(anyhow!("Not running inside a Kitty terminal"))
+        let capabilities = Capabilities::detect(&endpoint, to, timeout).await;
+        let instance = match &endpoint {
+            // kitty only talks over the terminal when it listens on no socket
+            Endpoint::Tty => std::env::var("KITTY_PID").ok().map(|pid| format!("pid:{}", pid)),
+            socket => Some(socket.to_string()),
+        };
+        let mut transport = NativeTransport::new(endpoint).with_timeout(timeout);
+        if let Some(password) = password {
+            transport = transport.with_encrypter(CommandEncrypter::from_env(password)?);
         }
+        debug!("kitty version {}: {:?}", capabilities, capabilities);
+        let mut client = Self::with_transport(transport)
+            .with_capabilities(capabilities)
+            .with_timeout(timeout);
+        client.instance = instance;
+        Ok(client)
     }
 
-    pub async fn get_buffer_content(&self) -> Result<String> {
-        let mut cmd = AsyncCommand::new("kitty");
-        cmd.arg("@").arg("get-text");
-        
-        if let Some(socket) = &self.socket_path {
-            cmd.arg("--to").arg(socket);
+    /// Builds a client on top of an arbitrary transport, e.g. a fake kitty.
+    /// It assumes a recent kitty until told otherwise with `with_capabilities`.
+    pub fn with_transport<T: KittyTransport + 'static>(transport: T) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            capabilities: Capabilities::default(),
+            timeout: DEFAULT_TIMEOUT,
+            instance: None,
         }
+    }
 
-        let output = cmd.output().await?;
-        
-        if output.status.success() {
-            Ok(String::from_utf8_lossy(&output.stdout).to_string())
-        } else {
-            Err(anyhow!("Failed to get buffer content: {}", 
-                       String::from_utf8_lossy(&output.stderr)))
-        }
+    /// How long to wait for each command before failing with a timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
     }
 
-    pub async fn jump_to_line(&self, line_number: u64) -> Result<()> {
-        let mut cmd = AsyncCommand::new("kitty");
-        cmd.arg("@").arg("scroll-to-line").arg(line_number.to_string());
-        
-        if let Some(socket) = &self.socket_path {
-            cmd.arg("--to").arg(socket);
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Names the connected kitty the same way whether we run inside it or
+    /// reach it from outside: by its socket address, or by its pid when it
+    /// has none and we talk over the terminal. `None` for other transports.
+    pub fn instance(&self) -> Option<&str> {
+        self.instance.as_deref()
+    }
+
+    /// What the connected kitty supports, as detected at startup.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    async fn detect_kitty_socket(to: Option<&str>) -> Result<Option<String>> {
+        let inside_kitty = std::env::var("KITTY_WINDOW_ID").is_ok();
+
+        // Inside kitty without an explicit socket, talk to our own instance
+        // over the terminal rather than whatever sockets a scan turns up
+        if inside_kitty && to.is_none() && std::env::var_os("KITTY_LISTEN_ON").is_none() {
+            return Ok(None);
         }
 
-        let output = cmd.output().await?;
-        
-        if !output.status.success() {
-            return Err(anyhow!("Failed to jump to line: {}", 
-                              String::from_utf8_lossy(&output.stderr)));
+        let candidates = discovery::socket_candidates(to);
+        match candidates.len() {
+            0 if !discovery::kitty_installed() => Err(KittyError::KittyNotFound.into()),
+            0 => Err(KittyError::NotInKitty.into()),
+            1 => Ok(candidates.into_iter().next()),
+            _ if std::io::stdin().is_terminal() => {
+                let stdin = std::io::stdin();
+                discovery::choose_socket(&candidates, stdin.lock(), std::io::stderr()).map(Some)
+            }
+            _ => Err(anyhow!(
+                "Multiple kitty instances found, pick one with --to: {}",
+                candidates.join(", ")
+            )),
         }
+    }
 
-        Ok(())
+    /// Fetches `extent` of the text of `window_id`, or of our own window when `None`.
+    pub async fn get_buffer_content(&self, window_id: Option<u32>, extent: Extent) -> Result<String> {
+        self.get_text(KittyCommand::get_text(extent).for_window(window_id))
+            .await
+    }
+
+    /// Like `get_buffer_content`, with colours and attributes kept as SGR sequences.
+    pub async fn get_ansi_content(&self, window_id: Option<u32>, extent: Extent) -> Result<String> {
+        self.get_text(KittyCommand::get_ansi_text(extent).for_window(window_id))
+            .await
     }
 
-    pub async fn create_text_marker(&self, text: &str) -> Result<()> {
-        let mut cmd = AsyncCommand::new("kitty");
-        cmd.arg("@").arg("create-marker").arg("text").arg("1").arg(text);
-        
-        if let Some(socket) = &self.socket_path {
-            cmd.arg("--to").arg(socket);
+    async fn get_text(&self, command: KittyCommand) -> Result<String> {
+        self.execute(command).await?.into_text()
+    }
+
+    /// Scrolls `window_id` so that the match described by `target` is shown
+    /// at `position` in the viewport, returning how many rows the window is
+    /// then scrolled up by.
+    pub async fn jump_to_line(
+        &self,
+        window_id: Option<u32>,
+        target: &JumpTarget<'_>,
+        position: JumpPosition,
+    ) -> Result<usize> {
+        // Re-capture with wrap markers: scrolling counts screen rows, and the
+        // scrollback may have changed since the search capture. Without them,
+        // wrapped lines put the match a little off its intended position.
+        let mut command = if self.capabilities.wrap_markers {
+            KittyCommand::get_wrapped_text(Extent::All)
+        } else {
+            KittyCommand::get_text(Extent::All)
+        };
+        // Command output is found again by its shell integration marks
+        let by_marks = matches!(target.extent, Extent::LastCmdOutput | Extent::LastVisitedCmdOutput)
+            && self.capabilities.ansi_text;
+        if by_marks {
+            command = command.with_ansi();
         }
+        let wrapped = self.get_text(command.for_window(window_id)).await?;
+        let map = if by_marks {
+            ScrollbackMap::parse_ansi(&wrapped)
+        } else {
+            ScrollbackMap::parse(&wrapped)
+        };
+        let row = map
+            .locate(target)
+            .and_then(|idx| map.row_of(idx))
+            .ok_or_else(|| anyhow!("Failed to jump to line: the match is no longer in the scrollback"))?;
+
+        let viewport_rows = self.viewport_rows(window_id).await?;
+        let top = map.top_row_for(row, viewport_rows, position);
 
-        let output = cmd.output().await?;
-        
-        if !output.status.success() {
-            return Err(anyhow!("Failed to create marker: {}", 
-                              String::from_utf8_lossy(&output.stderr)));
+        self.scroll(window_id, ScrollAmount::Start).await?;
+        if top > 0 {
+            self.scroll(window_id, ScrollAmount::Lines(top as i64)).await?;
         }
+        Ok(map.scrolled_by_at(top, viewport_rows))
+    }
 
+    pub async fn scroll(&self, window_id: Option<u32>, amount: ScrollAmount) -> Result<()> {
+        self.execute(KittyCommand::scroll_window(amount).for_window(window_id))
+            .await?;
         Ok(())
     }
 
-    pub async fn remove_marker(&self) -> Result<()> {
-        let mut cmd = AsyncCommand::new("kitty");
-        cmd.arg("@").arg("remove-marker");
-        
-        if let Some(socket) = &self.socket_path {
-            cmd.arg("--to").arg(socket);
-        }
+    /// Height in rows of `window_id`, or of our own window when `None`.
+    async fn viewport_rows(&self, window_id: Option<u32>) -> Result<usize> {
+        let tree = self.list_windows().await?;
+        let window = match window_id {
+            Some(id) => tree.window(id),
+            None => tree.self_window(),
+        };
+        window
+            .map(|window| window.lines as usize)
+            .filter(|&rows| rows > 0)
+            .ok_or_else(|| anyhow!("Could not determine the height of the kitty window"))
+    }
 
-        let output = cmd.output().await?;
-        
-        if !output.status.success() {
-            return Err(anyhow!("Failed to remove marker: {}", 
-                              String::from_utf8_lossy(&output.stderr)));
-        }
+    /// Fetches `extent` of the text of every window but our own, concurrently.
+    /// Windows that cannot be read, e.g. because they closed meanwhile, are
+    /// left out.
+    pub async fn get_all_window_contents(&self, extent: Extent) -> Result<Vec<WindowText>> {
+        let tree = self.list_windows().await?;
+        let windows: Vec<(u32, u32)> = tree
+            .tabs()
+            .flat_map(|tab| {
+                tab.windows
+                    .iter()
+                    .filter(|window| !window.is_self)
+                    .map(move |window| (window.id, tab.id))
+            })
+            .collect();
 
+        let fetches = windows.into_iter().map(|(window_id, tab_id)| async move {
+            match self.get_buffer_content(Some(window_id), extent).await {
+                Ok(text) => Some(WindowText {
+                    window_id,
+                    tab_id,
+                    text,
+                }),
+                Err(e) => {
+                    debug!("Skipping window {}: {}", window_id, e);
+                    None
+                }
+            }
+        });
+        Ok(join_all(fetches).await.into_iter().flatten().collect())
+    }
+
+    /// Brings `window_id` to the front, switching tab and OS window if needed.
+    pub async fn focus_window(&self, window_id: u32) -> Result<()> {
+        self.execute(KittyCommand::focus_window(window_id)).await?;
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub async fn get_window_info(&self) -> Result<Value> {
-        let mut cmd = AsyncCommand::new("kitty");
-        cmd.arg("@").arg("ls");
-        
-        if let Some(socket) = &self.socket_path {
-            cmd.arg("--to").arg(socket);
+    pub async fn create_marker(&self, window_id: Option<u32>, spec: &MarkerSpec) -> Result<()> {
+        self.execute(KittyCommand::create_marker(spec).for_window(window_id))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_marker(&self, window_id: Option<u32>) -> Result<()> {
+        self.execute(KittyCommand::remove_marker().for_window(window_id))
+            .await?;
+        Ok(())
+    }
+
+    /// Opens a new kitty window running `args`; returns the new window's id.
+    pub async fn launch(
+        &self,
+        launch_type: LaunchType,
+        next_to: Option<u32>,
+        env: &[(&str, &str)],
+        args: Vec<String>,
+    ) -> Result<Option<u32>> {
+        let command = env
+            .iter()
+            .fold(KittyCommand::launch(launch_type, next_to, args), |command, (name, value)| {
+                command.with_env(name, value)
+            });
+        self.execute(command).await?.into_window_id()
+    }
+
+    /// All OS windows, tabs and windows of the kitty instance.
+    pub async fn list_windows(&self) -> Result<WindowTree> {
+        self.list_matching(None).await
+    }
+
+    /// Auto-detects the window to search: the one underneath our overlay, or
+    /// the previously active window when running in a split.
+    pub async fn detect_target_window(&self) -> Result<Option<u32>> {
+        Ok(self.list_windows().await?.find_target_window())
+    }
+
+    /// Resolves a kitty match expression such as `title:logs` to a window id.
+    pub async fn resolve_match(&self, spec: &str) -> Result<u32> {
+        self.list_matching(Some(spec))
+            .await?
+            .windows()
+            .map(|window| window.id)
+            .next()
+            .ok_or_else(|| anyhow!("No kitty window matches {}", spec))
+    }
+
+    async fn list_matching(&self, spec: Option<&str>) -> Result<WindowTree> {
+        let mut command = KittyCommand::list_windows();
+        if let Some(spec) = spec {
+            command = command.matching(spec);
         }
+        self.execute(command).await?.into_windows()
+    }
 
-        let output = cmd.output().await?;
-        
-        if output.status.success() {
-            let json_str = String::from_utf8_lossy(&output.stdout);
-            Ok(serde_json::from_str(&json_str)?)
-        } else {
-            Err(anyhow!("Failed to get window info: {}", 
-                       String::from_utf8_lossy(&output.stderr)))
+    /// Sends `command` to kitty and decodes the reply. All other methods go
+    /// through here, so every command fails with a [`KittyError`].
+    ///
+    /// Reads that are safe to repeat are retried when kitty is busy or
+    /// unreachable. Each attempt runs in a `kitty` span, so `--debug` logs
+    /// how long it took.
+    pub async fn execute(&self, command: KittyCommand) -> Result<Response> {
+        let attempts = if is_idempotent(&command.cmd) { READ_ATTEMPTS } else { 1 };
+        let mut attempt = 1;
+        loop {
+            let span = debug_span!("kitty", cmd = %command.cmd, attempt);
+            match self.send(&command).instrument(span).await {
+                Ok(data) => return Response::parse(&command.cmd, data),
+                Err(e) if attempt < attempts && is_transient(&e) => {
+                    debug!("Retrying `{}`: {}", command.cmd, e);
+                    tokio::time::sleep(RETRY_DELAY * attempt).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn send(&self, command: &KittyCommand) -> Result<Value> {
+        let response = match tokio::time::timeout(self.timeout, self.transport.send(command)).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) if e.is::<KittyError>() => return Err(e),
+            Ok(Err(e)) => return Err(KittyError::Unreachable { message: e.to_string() }.into()),
+            Err(_) => {
+                return Err(KittyError::Timeout {
+                    command: command.cmd.clone(),
+                    timeout: self.timeout,
+                }
+                .into())
+            }
+        };
+        if response.ok {
+            return Ok(response.data.unwrap_or(Value::Null));
         }
+
+        let error = response.error.unwrap_or_else(|| "unknown error".to_string());
+        Err(KittyError::from_reply(&command.cmd, &error).into())
     }
 
     #[allow(dead_code)]
-    pub fn is_available() -> bool {
-        Command::new("kitty")
-            .arg("@")
-            .arg("ls")
-            .output()
-            .map(|output| output.status.success())
-            .unwrap_or(false)
-    }
-}
\ No newline at end of file
+    pub async fn is_available(&self) -> bool {
+        self.list_windows().await.is_ok()
+    }
+}
+
+/// Commands that only read state, so sending them twice does no harm.
+fn is_idempotent(cmd: &str) -> bool {
+    matches!(cmd, "get-text" | "ls" | "get-colors")
+}
+
+/// Failures that may go away by themselves, unlike a refusal from kitty.
+fn is_transient(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<KittyError>(),
+        Some(KittyError::Timeout { .. } | KittyError::Unreachable { .. })
+    )
+}
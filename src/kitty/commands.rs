@@ -1,7 +1,12 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use std::collections::HashMap;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::kitty::marker::MarkerSpec;
+
+use crate::kitty::protocol::RC_PROTOCOL_VERSION;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KittyCommand {
     pub cmd: String,
     pub args: Vec<String>,
@@ -28,12 +33,53 @@ impl KittyCommand {
         self
     }
 
-    pub fn get_text() -> Self {
-        Self::new("get-text")
+    /// Targets the command at a specific window instead of the caller's own.
+    pub fn for_window(mut self, window_id: Option<u32>) -> Self {
+        if let Some(id) = window_id {
+            self = self.matching(&format!("id:{}", id));
+        }
+        self
+    }
+
+    fn with_flag(mut self, flag: &str) -> Self {
+        // Options must come before any positional arguments
+        self.args.insert(0, flag.to_string());
+        self
+    }
+
+    pub fn matching(mut self, spec: &str) -> Self {
+        // Options must come before any positional arguments
+        self.args.insert(0, format!("--match={}", spec));
+        self
+    }
+
+    pub fn get_text(extent: Extent) -> Self {
+        Self::new("get-text").with_args(vec![format!("--extent={}", extent.as_str())])
+    }
+
+    /// Like `get_text`, but keeps colours and text attributes as SGR sequences.
+    pub fn get_ansi_text(extent: Extent) -> Self {
+        Self::get_text(extent).with_ansi()
+    }
+
+    /// Adds `--ansi` to a `get-text`, which also keeps shell integration marks.
+    pub fn with_ansi(self) -> Self {
+        self.with_flag("--ansi")
     }
 
-    pub fn scroll_to_line(line: u64) -> Self {
-        Self::new("scroll-to-line").with_args(vec![line.to_string()])
+    /// Text with `\r` at every soft wrap, so lines can be mapped to screen rows.
+    pub fn get_wrapped_text(extent: Extent) -> Self {
+        Self::get_text(extent).with_flag("--add-wrap-markers")
+    }
+
+    pub fn scroll_window(amount: ScrollAmount) -> Self {
+        let arg = match amount {
+            ScrollAmount::Start => "start".to_string(),
+            ScrollAmount::End => "end".to_string(),
+            ScrollAmount::Lines(n) if n < 0 => format!("{}-", -n),
+            ScrollAmount::Lines(n) => n.to_string(),
+        };
+        Self::new("scroll-window").with_args(vec![arg])
     }
 
     pub fn list_windows() -> Self {
@@ -48,15 +94,22 @@ impl KittyCommand {
         Self::new("set-window-title").with_args(vec![title.to_string()])
     }
 
-    pub fn resize_window(width: u32, height: u32) -> Self {
+    /// Grows (or with a negative `increment`, shrinks) a window by that many
+    /// cells along `axis`. kitty only resizes windows in splits or tiled layouts.
+    pub fn resize_window(axis: ResizeAxis, increment: i32) -> Self {
+        let axis = match axis {
+            ResizeAxis::Horizontal => "horizontal",
+            ResizeAxis::Vertical => "vertical",
+            ResizeAxis::Reset => "reset",
+        };
         Self::new("resize-window").with_args(vec![
-            format!("--width={}", width),
-            format!("--height={}", height),
+            format!("--axis={}", axis),
+            format!("--increment={}", increment),
         ])
     }
 
-    pub fn focus_window(window_id: &str) -> Self {
-        Self::new("focus-window").with_args(vec![format!("--match=id:{}", window_id)])
+    pub fn focus_window(window_id: u32) -> Self {
+        Self::new("focus-window").for_window(Some(window_id))
     }
 
     pub fn get_colors() -> Self {
@@ -72,61 +125,336 @@ impl KittyCommand {
         Self::new("set-colors").with_args(color_args)
     }
 
-    pub fn create_marker(text: &str) -> Self {
-        Self::new("create-marker").with_args(vec![text.to_string()])
+    pub fn create_marker(spec: &MarkerSpec) -> Self {
+        Self::new("create-marker").with_args(spec.to_args())
     }
 
     pub fn remove_marker() -> Self {
         Self::new("remove-marker")
     }
 
+    /// Starts `args` in a new kitty window placed according to `launch_type`,
+    /// next to (or on top of) `next_to`.
+    pub fn launch(launch_type: LaunchType, next_to: Option<u32>, args: Vec<String>) -> Self {
+        let mut cmd_args = match launch_type {
+            LaunchType::Overlay => vec!["--type=overlay".to_string()],
+            LaunchType::Split => vec!["--type=window".to_string(), "--location=hsplit".to_string()],
+            LaunchType::OsWindow => vec!["--type=os-window".to_string()],
+            LaunchType::Background => vec!["--type=background".to_string()],
+        };
+        if let Some(id) = next_to {
+            cmd_args.push(format!("--next-to=id:{}", id));
+        }
+        // Lets the launched process talk to kitty over its own terminal
+        cmd_args.push("--allow-remote-control".to_string());
+        // kitty's payload calls it `window_title`; `--title` would be dropped
+        cmd_args.push("--window-title=kitty-fast-search".to_string());
+        cmd_args.extend(args);
+        Self::new("launch").with_args(cmd_args)
+    }
+
+    /// Sets `name` in the environment of the program `launch` starts. Unlike
+    /// its arguments, other users cannot read it with `ps`.
+    pub fn with_env(mut self, name: &str, value: &str) -> Self {
+        // Options have to come before the program
+        self.args.insert(0, format!("--env={}={}", name, value));
+        self
+    }
+
+    /// Runs a mappable kitty action, e.g. `scroll_to_prompt -1`.
+    pub fn action(action: &str, args: &[&str]) -> Self {
+        let mut cmd_args = vec![action.to_string()];
+        cmd_args.extend(args.iter().map(|arg| arg.to_string()));
+        Self::new("action").with_args(cmd_args)
+    }
+
+    /// There is no remote-control command for this, so it goes through the
+    /// `scroll_to_prompt` action.
     pub fn scroll_to_prompt(direction: ScrollDirection) -> Self {
         let dir_arg = match direction {
-            ScrollDirection::Previous => "prev",
-            ScrollDirection::Next => "next",
+            ScrollDirection::Previous => "-1",
+            ScrollDirection::Next => "1",
         };
-        Self::new("scroll-to-prompt").with_args(vec![dir_arg.to_string()])
+        Self::action("scroll_to_prompt", &[dir_arg])
     }
 
+    /// The equivalent `kitty @` invocation, for logging and debugging.
     pub fn to_command_line(&self) -> Vec<String> {
         let mut cmd_line = vec!["kitty".to_string(), "@".to_string(), self.cmd.clone()];
         cmd_line.extend(self.args.clone());
+        if let Some(payload) = &self.payload {
+            cmd_line.push(payload.clone());
+        }
         cmd_line
     }
+
+    /// Builds the JSON message sent inside a `@kitty-cmd` envelope.
+    pub fn to_rc_message(&self) -> Value {
+        json!({
+            "cmd": self.cmd,
+            "version": RC_PROTOCOL_VERSION,
+            "no_response": false,
+            "payload": self.rc_payload(),
+        })
+    }
+
+    /// Translates the command-line style `args` into kitty's payload object.
+    ///
+    /// `--name=value` options become `name: value`, bare `--flag`s become
+    /// `flag: true`, and positional arguments are mapped per command.
+    fn rc_payload(&self) -> Map<String, Value> {
+        let mut payload = Map::new();
+        let mut positional = Vec::new();
+
+        for arg in &self.args {
+            // Like kitty's own parser, options end at the first positional
+            // argument, so a launched program keeps its own flags
+            if !positional.is_empty() {
+                positional.push(arg.clone());
+                continue;
+            }
+            match arg.strip_prefix("--") {
+                Some(opt) => {
+                    let (name, value) = match opt.split_once('=') {
+                        Some((name, value)) => (name, option_value(value)),
+                        None => (opt, Value::Bool(true)),
+                    };
+                    if name == "env" {
+                        // The only option given more than once, as a list
+                        let vars = payload.entry("env").or_insert_with(|| json!([]));
+                        if let Value::Array(vars) = vars {
+                            vars.push(Value::String(opt["env=".len()..].to_string()));
+                        }
+                    } else {
+                        payload.insert(payload_key(name), value);
+                    }
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+
+        match self.cmd.as_str() {
+            "action" => {
+                // `action` names its window option differently
+                if let Some(spec) = payload.remove("match") {
+                    payload.insert("match_window".to_string(), spec);
+                }
+                payload.insert("action".to_string(), json!(positional.join(" ")));
+            }
+            "create-marker" => {
+                payload.insert("marker_spec".to_string(), json!(positional));
+            }
+            "scroll-window" => {
+                if let Some(amount) = positional.first() {
+                    payload.insert("amount".to_string(), scroll_amount(amount));
+                }
+            }
+            "set-window-title" => {
+                payload.insert("title".to_string(), json!(positional.join(" ")));
+            }
+            "set-colors" => {
+                // Like `action`, set-colors matches windows with `match_window`
+                if let Some(spec) = payload.remove("match") {
+                    payload.insert("match_window".to_string(), spec);
+                }
+                let colors: Map<String, Value> = positional
+                    .iter()
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(key, value)| (key.to_string(), color_value(value)))
+                    .collect();
+                payload.insert("colors".to_string(), Value::Object(colors));
+            }
+            "send-text" => {
+                if let Some(text) = &self.payload {
+                    payload.insert("data".to_string(), json!(format!("text:{}", text)));
+                }
+            }
+            _ => {
+                if !positional.is_empty() {
+                    payload.insert("args".to_string(), json!(positional));
+                }
+            }
+        }
+
+        payload
+    }
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
-pub enum ScrollDirection {
-    Previous,
-    Next,
+/// Converts a `kitty @ scroll-window` amount such as `start`, `5` or `3-`
+/// into the `[amount, unit]` pair kitty expects.
+fn scroll_amount(arg: &str) -> Value {
+    if arg == "start" || arg == "end" {
+        return json!([arg, null]);
+    }
+    let count: i64 = arg.trim_end_matches(['+', '-']).parse().unwrap_or(0);
+    let count = if arg.ends_with('-') { -count } else { count };
+    json!([count, "l"])
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
-pub struct WindowInfo {
-    pub id: u32,
-    pub title: String,
-    pub pid: u32,
-    pub cwd: String,
-    pub cmdline: Vec<String>,
-    pub env: HashMap<String, String>,
+/// Converts a colour such as `#ff8000` into the 24-bit RGB integer kitty
+/// expects, or `null` for `none`. Anything else is passed on for kitty to
+/// reject.
+fn color_value(value: &str) -> Value {
+    if value == "none" {
+        return Value::Null;
+    }
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let rgb = match hex.len() {
+        6 => u32::from_str_radix(hex, 16).ok(),
+        // #rgb, each digit doubled
+        3 => u32::from_str_radix(hex, 16)
+            .ok()
+            .map(|short| (short >> 8) * 0x110000 + ((short >> 4) & 0xf) * 0x1100 + (short & 0xf) * 0x11),
+        _ => None,
+    };
+    rgb.map(Value::from).unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+fn payload_key(option: &str) -> String {
+    match option {
+        "add-wrap-markers" => "wrap_markers".to_string(),
+        other => other.replace('-', "_"),
+    }
+}
+
+fn option_value(value: &str) -> Value {
+    value
+        .parse::<i64>()
+        .map(Value::from)
+        .unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+/// Argument to `scroll-window`: an absolute position or a relative number of
+/// lines, positive scrolling down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrollAmount {
+    Start,
+    End,
+    Lines(i64),
+}
+
+/// How much of a window's text `get-text` returns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Extent {
+    /// Only the lines currently on screen
+    Screen,
+    /// The whole scrollback plus the screen
+    #[default]
+    All,
+    /// Output of the last command (needs shell integration)
+    #[value(name = "last_cmd_output")]
+    LastCmdOutput,
+    /// Output of the command last scrolled to or clicked (needs shell integration)
+    #[value(name = "last_visited_cmd_output")]
+    LastVisitedCmdOutput,
+    /// The current selection
+    Selection,
+}
+
+impl Extent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Extent::Screen => "screen",
+            Extent::All => "all",
+            Extent::LastCmdOutput => "last_cmd_output",
+            Extent::LastVisitedCmdOutput => "last_visited_cmd_output",
+            Extent::Selection => "selection",
+        }
+    }
+
+    /// The extent after this one, for cycling through them in the overlay.
+    pub fn next(self) -> Self {
+        match self {
+            Extent::All => Extent::Screen,
+            Extent::Screen => Extent::LastCmdOutput,
+            Extent::LastCmdOutput => Extent::LastVisitedCmdOutput,
+            Extent::LastVisitedCmdOutput => Extent::Selection,
+            Extent::Selection => Extent::All,
+        }
+    }
+}
+
+/// Where `--launch` opens the search UI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LaunchType {
+    /// On top of the searched window, like iTerm's find bar
+    Overlay,
+    /// In a horizontal split below the searched window
+    Split,
+    /// In a separate OS window
+    OsWindow,
+    /// As a background process without a window
+    #[value(skip)]
+    Background,
 }
 
-#[derive(Debug, Deserialize)]
+/// Axis for `resize-window`; `Reset` restores the layout's default sizes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
-pub struct TabInfo {
-    pub id: u32,
-    pub title: String,
-    pub layout: String,
-    pub windows: Vec<WindowInfo>,
-    pub active_window: u32,
+pub enum ResizeAxis {
+    Horizontal,
+    Vertical,
+    Reset,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
-pub struct OSWindowInfo {
-    pub id: u32,
-    pub tabs: Vec<TabInfo>,
-    pub active_tab: u32,
+pub enum ScrollDirection {
+    Previous,
+    Next,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line_includes_payload() {
+        let command = KittyCommand::send_text("ls\n").for_window(Some(3));
+        assert_eq!(
+            command.to_command_line(),
+            vec!["kitty", "@", "send-text", "--match=id:3", "ls\n"]
+        );
+        assert_eq!(command.to_rc_message()["payload"]["data"], "text:ls\n");
+    }
+
+    #[test]
+    fn test_resize_window_payload() {
+        let payload = KittyCommand::resize_window(ResizeAxis::Vertical, -2).to_rc_message()["payload"].clone();
+        assert_eq!(payload, json!({"axis": "vertical", "increment": -2}));
+    }
+
+    #[test]
+    fn test_scroll_to_prompt_runs_action() {
+        let message = KittyCommand::scroll_to_prompt(ScrollDirection::Previous)
+            .for_window(Some(5))
+            .to_rc_message();
+        assert_eq!(message["cmd"], "action");
+        assert_eq!(
+            message["payload"],
+            json!({"action": "scroll_to_prompt -1", "match_window": "id:5"})
+        );
+    }
+
+    #[test]
+    fn test_set_colors_payload() {
+        let colors = HashMap::from([
+            ("mark1_background".to_string(), "#ff8000".to_string()),
+            ("mark2_foreground".to_string(), "#0f0".to_string()),
+            ("selection_background".to_string(), "none".to_string()),
+        ]);
+        let message = KittyCommand::set_colors(colors).for_window(Some(4)).to_rc_message();
+        assert_eq!(message["cmd"], "set-colors");
+        assert_eq!(
+            message["payload"],
+            json!({
+                "colors": {
+                    "mark1_background": 0xff8000,
+                    "mark2_foreground": 0x00ff00,
+                    "selection_background": null,
+                },
+                "match_window": "id:4",
+            })
+        );
+    }
 }
\ No newline at end of file
@@ -0,0 +1,162 @@
+use anyhow::{Result, anyhow};
+use std::io::{BufRead, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+/// Finds kitty remote-control sockets, most specific source first.
+///
+/// An explicit `--to` address or `KITTY_LISTEN_ON` wins outright. Otherwise
+/// the per-user socket locations are scanned, which finds every kitty started
+/// with `listen_on unix:/tmp/kitty` (kitty appends `-<pid>` to the path) or
+/// an abstract `unix:@kitty` address.
+pub fn socket_candidates(to: Option<&str>) -> Vec<String> {
+    if let Some(address) = to {
+        return vec![address.to_string()];
+    }
+    if let Ok(address) = std::env::var("KITTY_LISTEN_ON") {
+        if !address.is_empty() {
+            return vec![address];
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for dir in socket_dirs() {
+        candidates.extend(scan_socket_dir(&dir));
+    }
+    candidates.extend(scan_abstract_sockets());
+    candidates.dedup();
+    candidates
+}
+
+fn socket_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![std::env::temp_dir()];
+    if let Some(runtime) = dirs::runtime_dir() {
+        if !dirs.contains(&runtime) {
+            dirs.push(runtime);
+        }
+    }
+    dirs
+}
+
+/// Returns `unix:` addresses for sockets named `kitty*` in `dir`.
+pub fn scan_socket_dir(dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut sockets: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("kitty"))
+        .filter(|entry| {
+            entry
+                .file_type()
+                .map(|file_type| file_type.is_socket())
+                .unwrap_or(false)
+        })
+        .map(|entry| format!("unix:{}", entry.path().display()))
+        .collect();
+    sockets.sort();
+    sockets
+}
+
+/// Lists abstract `@kitty*` sockets from `/proc/net/unix` (Linux only).
+fn scan_abstract_sockets() -> Vec<String> {
+    let Ok(table) = std::fs::read_to_string("/proc/net/unix") else {
+        return Vec::new();
+    };
+
+    let mut sockets: Vec<String> = table
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(7))
+        .filter(|path| path.starts_with("@kitty"))
+        .map(|path| format!("unix:{}", path))
+        .collect();
+    sockets.sort();
+    sockets.dedup();
+    sockets
+}
+
+/// Whether a `kitty` executable is on `PATH`.
+pub fn kitty_installed() -> bool {
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|dir| dir.join("kitty").is_file()))
+        .unwrap_or(false)
+}
+
+/// Asks the user which socket to use when several kitty instances listen.
+pub fn choose_socket<R: BufRead, W: Write>(
+    candidates: &[String],
+    mut input: R,
+    mut output: W,
+) -> Result<String> {
+    writeln!(output, "Multiple kitty instances found:")?;
+    for (i, candidate) in candidates.iter().enumerate() {
+        writeln!(output, "  {}) {}", i + 1, candidate)?;
+    }
+
+    loop {
+        write!(output, "Select instance [1-{}]: ", candidates.len())?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(anyhow!("No kitty instance selected"));
+        }
+        match line.trim().parse::<usize>() {
+            Ok(n) if (1..=candidates.len()).contains(&n) => {
+                return Ok(candidates[n - 1].clone());
+            }
+            _ => writeln!(output, "Please enter a number between 1 and {}", candidates.len())?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_explicit_address_wins() {
+        let candidates = socket_candidates(Some("unix:/tmp/kitty-explicit"));
+        assert_eq!(candidates, vec!["unix:/tmp/kitty-explicit"]);
+    }
+
+    #[test]
+    fn test_scan_finds_kitty_sockets_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let _a = UnixListener::bind(dir.path().join("kitty-101")).unwrap();
+        let _b = UnixListener::bind(dir.path().join("kitty-202")).unwrap();
+        let _other = UnixListener::bind(dir.path().join("other.sock")).unwrap();
+        std::fs::write(dir.path().join("kitty-not-a-socket"), "").unwrap();
+
+        let found = scan_socket_dir(dir.path());
+        assert_eq!(
+            found,
+            vec![
+                format!("unix:{}", dir.path().join("kitty-101").display()),
+                format!("unix:{}", dir.path().join("kitty-202").display()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_choose_socket_retries_until_valid() {
+        let candidates = vec!["unix:/tmp/kitty-1".to_string(), "unix:/tmp/kitty-2".to_string()];
+        let mut output = Vec::new();
+        let chosen = choose_socket(&candidates, Cursor::new("7\nx\n2\n"), &mut output).unwrap();
+
+        assert_eq!(chosen, "unix:/tmp/kitty-2");
+        let prompt = String::from_utf8(output).unwrap();
+        assert!(prompt.contains("1) unix:/tmp/kitty-1"));
+        assert!(prompt.contains("Please enter a number"));
+    }
+
+    #[test]
+    fn test_choose_socket_without_input() {
+        let candidates = vec!["unix:/tmp/kitty-1".to_string(), "unix:/tmp/kitty-2".to_string()];
+        assert!(choose_socket(&candidates, Cursor::new(""), Vec::new()).is_err());
+    }
+}
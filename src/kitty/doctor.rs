@@ -0,0 +1,315 @@
+use anyhow::Result;
+use std::fmt;
+
+use crate::kitty::capabilities::{self, parse_version, Capabilities, MIN_KITTY_VERSION};
+use crate::kitty::commands::{Extent, LaunchType, ScrollAmount};
+use crate::kitty::error::{hint_for, KittyError};
+use crate::kitty::marker::MarkerSpec;
+use crate::kitty::KittyClient;
+use crate::pins::PinStore;
+
+/// What the environment tells us about kitty, gathered before talking to it.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// Output of `kitty --version`, when kitty is on `PATH`
+    pub kitty_version: Option<String>,
+    pub window_id: Option<String>,
+    pub listen_on: Option<String>,
+}
+
+impl Environment {
+    pub async fn detect() -> Self {
+        Self {
+            kitty_version: capabilities::kitty_version().await,
+            window_id: std::env::var("KITTY_WINDOW_ID").ok(),
+            listen_on: std::env::var("KITTY_LISTEN_ON").ok().filter(|address| !address.is_empty()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// The outcome of one check, with how to fix it when it did not pass.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Pass,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: &str, detail: impl Into<String>, fix: Option<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Warn,
+            detail: detail.into(),
+            fix,
+        }
+    }
+
+    fn fail(name: &str, detail: impl Into<String>, fix: Option<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: Status::Fail,
+            detail: detail.into(),
+            fix,
+        }
+    }
+
+    /// Passes when `result` is `Ok`, otherwise fails with the error and its hint.
+    fn from_result<T>(name: &str, detail: &str, result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => Self::pass(name, detail),
+            Err(e) => Self::fail(name, e.to_string(), hint_for(e)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Whether nothing failed; warnings are allowed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.status != Status::Fail)
+    }
+
+    #[allow(dead_code)]
+    pub fn check(&self, name: &str) -> Option<&Check> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+
+    fn count(&self, status: Status) -> usize {
+        self.checks.iter().filter(|check| check.status == status).count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.checks.iter().map(|check| check.name.len()).max().unwrap_or(0);
+        for check in &self.checks {
+            let label = match check.status {
+                Status::Pass => "[ok]  ",
+                Status::Warn => "[warn]",
+                Status::Fail => "[fail]",
+            };
+            writeln!(f, "{} {:width$}  {}", label, check.name, check.detail, width = width)?;
+            if let Some(fix) = &check.fix {
+                writeln!(f, "       {:width$}  fix: {}", "", fix, width = width)?;
+            }
+        }
+        write!(
+            f,
+            "\n{} passed, {} warnings, {} failed",
+            self.count(Status::Pass),
+            self.count(Status::Warn),
+            self.count(Status::Fail)
+        )
+    }
+}
+
+/// Checks everything the search needs from kitty: the binary, how we reach
+/// it, permission to read and highlight windows, markers and shell
+/// integration.
+///
+/// Nothing is changed in kitty unless `probe_launch` is set, which also
+/// checks scrolling, focusing and launching by doing each once: scrolling by
+/// no lines, focusing the window that has focus and launching `true` in the
+/// background.
+///
+/// `client` is the outcome of connecting; without a connection only the
+/// environment is checked. `pins` tells which windows keep highlights that
+/// the marker check must not remove.
+pub async fn diagnose(
+    environment: &Environment,
+    client: &Result<KittyClient>,
+    pins: &PinStore,
+    probe_launch: bool,
+) -> Report {
+    let mut report = Report::default();
+    report.checks.push(check_binary(environment));
+    report.checks.push(check_features(environment));
+    report.checks.push(check_environment(environment));
+
+    let client = match client {
+        Ok(client) => client,
+        Err(e) => {
+            report.checks.push(Check::fail("connection", e.to_string(), hint_for(e)));
+            return report;
+        }
+    };
+
+    let tree = client.list_windows().await;
+    report.checks.push(Check::from_result("ls", "can list windows", &tree));
+    let window = tree.as_ref().ok().and_then(|tree| tree.self_window().or_else(|| tree.active_window()));
+    let window_id = window.map(|window| window.id);
+
+    let text = client.get_buffer_content(window_id, Extent::Screen).await;
+    report.checks.push(Check::from_result("get-text", "can read window text", &text));
+
+    report.checks.push(check_markers(client, window_id, pins).await);
+
+    if probe_launch {
+        let scroll = client.scroll(window_id, ScrollAmount::Lines(0)).await;
+        report.checks.push(Check::from_result("scroll-window", "can scroll windows", &scroll));
+
+        if let Some(id) = window_id {
+            let focus = client.focus_window(id).await;
+            report.checks.push(Check::from_result("focus-window", "can focus windows", &focus));
+        }
+
+        let launch = client
+            .launch(LaunchType::Background, None, &[], vec!["true".to_string()])
+            .await;
+        report.checks.push(Check::from_result("launch", "can open the search window", &launch));
+    }
+
+    if let Some(window) = window {
+        report.checks.push(if window.at_prompt || !window.last_reported_cmdline.is_empty() {
+            Check::pass("shell integration", "prompts are marked (OSC 133)")
+        } else {
+            Check::warn(
+                "shell integration",
+                "no prompt marks, so searching a command's output is unavailable",
+                Some(
+                    "set `shell_integration enabled` in kitty.conf, or source kitty's \
+                     shell integration script in your shell's rc file"
+                        .to_string(),
+                ),
+            )
+        });
+    }
+    report
+}
+
+fn check_binary(environment: &Environment) -> Check {
+    let Some(version) = &environment.kitty_version else {
+        return Check::fail(
+            "kitty binary",
+            "kitty is not on PATH",
+            KittyError::KittyNotFound.hint(),
+        );
+    };
+    // Newer features are checked separately, as kitty lacking them only
+    // degrades the search
+    if Capabilities::for_version(parse_version(version)).is_supported() {
+        Check::pass("kitty binary", version.clone())
+    } else {
+        Check::fail(
+            "kitty binary",
+            version.clone(),
+            Some(format!(
+                "update kitty to {} or newer",
+                MIN_KITTY_VERSION.map(|part| part.to_string()).join(".")
+            )),
+        )
+    }
+}
+
+/// Lists the features this kitty is too old for.
+fn check_features(environment: &Environment) -> Check {
+    let capabilities =
+        Capabilities::for_version(environment.kitty_version.as_deref().and_then(parse_version));
+    let missing: Vec<&str> = [
+        (capabilities.markers, "highlighting"),
+        (capabilities.scroll_window, "jumping to matches"),
+        (capabilities.wrap_markers, "exact jumps in wrapped lines"),
+        (capabilities.ansi_text, "--ansi and --style"),
+        (capabilities.last_cmd_output, "the last_cmd_output extent"),
+        (capabilities.last_visited_cmd_output, "the last_visited_cmd_output extent"),
+    ]
+    .into_iter()
+    .filter(|(supported, _)| !supported)
+    .map(|(_, feature)| feature)
+    .collect();
+
+    if missing.is_empty() {
+        Check::pass("features", "all supported")
+    } else {
+        Check::warn(
+            "features",
+            format!("kitty {} lacks {}", capabilities, missing.join(", ")),
+            Some("update kitty".to_string()),
+        )
+    }
+}
+
+fn check_environment(environment: &Environment) -> Check {
+    match (&environment.window_id, &environment.listen_on) {
+        (Some(id), _) => Check::pass("environment", format!("inside kitty window {}", id)),
+        (None, Some(address)) => Check::pass("environment", format!("KITTY_LISTEN_ON is {}", address)),
+        (None, None) => Check::warn(
+            "environment",
+            "neither KITTY_WINDOW_ID nor KITTY_LISTEN_ON is set",
+            KittyError::NotInKitty.hint(),
+        ),
+    }
+}
+
+/// Highlights with the same kind of marker a search creates, then removes it.
+///
+/// Removing a marker removes every highlight of the window, so a window with
+/// pins kept by `--keep-markers` is left alone and judged by its version.
+async fn check_markers(client: &KittyClient, window_id: Option<u32>, pins: &PinStore) -> Check {
+    if !pins.pins(window_id).is_empty() {
+        return if client.capabilities().markers {
+            Check::pass("markers", "not probed, the window has pinned terms")
+        } else {
+            Check::fail(
+                "markers",
+                format!("kitty {} has no markers", client.capabilities()),
+                Some("update kitty".to_string()),
+            )
+        };
+    }
+    let spec = MarkerSpec::for_search("kitty-fast-search doctor", false, false);
+    let result = match client.create_marker(window_id, &spec).await {
+        Ok(()) => client.remove_marker(window_id).await,
+        Err(e) => Err(e),
+    };
+    Check::from_result("markers", "can highlight matches", &result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_old_or_missing_kitty_fails() {
+        let old = Environment {
+            kitty_version: Some("kitty 0.13.3 created by Kovid Goyal".to_string()),
+            ..Environment::default()
+        };
+        let check = check_binary(&old);
+        assert_eq!(check.status, Status::Fail);
+        assert_eq!(check.fix.as_deref(), Some("update kitty to 0.14.0 or newer"));
+
+        // Older than the protocol we speak, but the search degrades instead
+        let degraded = Environment {
+            kitty_version: Some("kitty 0.21.2 created by Kovid Goyal".to_string()),
+            ..Environment::default()
+        };
+        assert_eq!(check_binary(&degraded).status, Status::Pass);
+        assert_eq!(check_features(&degraded).status, Status::Warn);
+
+        assert_eq!(check_binary(&Environment::default()).status, Status::Fail);
+        assert_eq!(check_environment(&Environment::default()).status, Status::Warn);
+    }
+}
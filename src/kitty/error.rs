@@ -0,0 +1,140 @@
+use std::time::Duration;
+use thiserror::Error;
+
+/// Ways talking to kitty can fail, each with a hint on how to fix it.
+#[derive(Debug, Error)]
+pub enum KittyError {
+    #[error("not running inside kitty, and no kitty socket was found")]
+    NotInKitty,
+
+    #[error("kitty does not seem to be installed")]
+    KittyNotFound,
+
+    #[error("kitty has remote control disabled")]
+    RemoteControlDisabled,
+
+    #[error("kitty denied `{command}`: {message}")]
+    PermissionDenied { command: String, message: String },
+
+    #[error("kitty does not know the `{command}` command")]
+    UnknownCommand { command: String },
+
+    #[error("kitty did not answer `{command}` within {}ms", timeout.as_millis())]
+    Timeout { command: String, timeout: Duration },
+
+    #[error("could not understand kitty's reply: {detail}")]
+    MalformedResponse { detail: String },
+
+    #[error("could not reach kitty: {message}")]
+    Unreachable { message: String },
+
+    #[error("`{command}` failed: {message}")]
+    CommandFailed { command: String, message: String },
+}
+
+impl KittyError {
+    /// Classifies the error message kitty replied to `command` with.
+    pub fn from_reply(command: &str, message: &str) -> Self {
+        let lower = message.to_lowercase();
+        let message = message.trim().to_string();
+        let command = command.to_string();
+
+        if lower.contains("remote control is disabled") {
+            KittyError::RemoteControlDisabled
+        } else if ["not authorized", "not allowed", "permission", "password"]
+            .iter()
+            .any(|needle| lower.contains(needle))
+        {
+            KittyError::PermissionDenied { command, message }
+        } else if lower.contains("unknown remote control command") || lower.contains("unknown command") {
+            KittyError::UnknownCommand { command }
+        } else {
+            KittyError::CommandFailed { command, message }
+        }
+    }
+
+    pub fn malformed(detail: impl ToString) -> Self {
+        KittyError::MalformedResponse {
+            detail: detail.to_string(),
+        }
+    }
+
+    /// What to change to make the error go away, if we know.
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            KittyError::NotInKitty => Some(
+                "run it from a kitty window, or add `listen_on unix:/tmp/kitty` to kitty.conf \
+                 and pass `--to unix:/tmp/kitty-<pid>`"
+                    .to_string(),
+            ),
+            KittyError::KittyNotFound => {
+                Some("install kitty from https://sw.kovidgoyal.net/kitty/binary/".to_string())
+            }
+            KittyError::RemoteControlDisabled => {
+                Some("add `allow_remote_control yes` to kitty.conf and restart kitty".to_string())
+            }
+            KittyError::PermissionDenied { command, .. } => Some(format!(
+                "allow it in kitty.conf with `remote_control_password \"<password>\" {}` \
+                 and pass the password with --password",
+                command
+            )),
+            KittyError::UnknownCommand { .. } => {
+                Some("update kitty; this command needs a newer version".to_string())
+            }
+            KittyError::Timeout { .. } => Some(
+                "check that `allow_remote_control` is enabled in kitty.conf; \
+                 kitty does not answer commands it ignores"
+                    .to_string(),
+            ),
+            KittyError::MalformedResponse { .. } => {
+                Some("this is likely a bug; please report it with the --debug output".to_string())
+            }
+            KittyError::Unreachable { .. } => Some(
+                "check that kitty is running and that --to or KITTY_LISTEN_ON matches \
+                 `listen_on` in kitty.conf"
+                    .to_string(),
+            ),
+            KittyError::CommandFailed { .. } => None,
+        }
+    }
+}
+
+/// The hint of the `KittyError` behind `error`, if there is one.
+pub fn hint_for(error: &anyhow::Error) -> Option<String> {
+    error.downcast_ref::<KittyError>().and_then(KittyError::hint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classifies_kitty_replies() {
+        assert!(matches!(
+            KittyError::from_reply("ls", "Remote control is disabled. Add allow_remote_control to your kitty.conf"),
+            KittyError::RemoteControlDisabled
+        ));
+        assert!(matches!(
+            KittyError::from_reply("get-text", "Not authorized: password incorrect"),
+            KittyError::PermissionDenied { .. }
+        ));
+        assert!(matches!(
+            KittyError::from_reply("frobnicate", "Unknown remote control command: frobnicate"),
+            KittyError::UnknownCommand { .. }
+        ));
+        assert!(matches!(
+            KittyError::from_reply("get-text", "No matching windows for expression: id:9"),
+            KittyError::CommandFailed { .. }
+        ));
+    }
+
+    #[test]
+    fn test_hints_name_the_fix() {
+        let denied = KittyError::from_reply("create-marker", "Not authorized");
+        assert!(denied.hint().unwrap().contains("remote_control_password \"<password>\" create-marker"));
+
+        let error = anyhow::Error::from(KittyError::RemoteControlDisabled);
+        assert!(hint_for(&error).unwrap().contains("allow_remote_control yes"));
+        assert_eq!(hint_for(&anyhow::anyhow!("other")), None);
+    }
+}
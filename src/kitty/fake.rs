@@ -0,0 +1,152 @@
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::kitty::commands::KittyCommand;
+use crate::kitty::protocol::RcResponse;
+use crate::kitty::transport::KittyTransport;
+
+/// Scripted in-memory kitty used by tests.
+///
+/// `get-text` replies are served from a queue: each call consumes the next
+/// scripted text, and the last one keeps being returned once the queue is
+/// down to a single entry, unless the command targets a window given its
+/// own text with `with_window_text`. `ls` returns the configured window tree. Every
+/// command is recorded so tests can assert on markers and scrolling, including
+/// ones left unanswered with `with_stall`.
+#[derive(Clone, Default)]
+pub struct FakeKitty {
+    state: Arc<Mutex<FakeState>>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    texts: VecDeque<String>,
+    window_texts: HashMap<u32, String>,
+    windows: Value,
+    failures: HashMap<String, String>,
+    stalls: HashMap<String, usize>,
+    calls: Vec<KittyCommand>,
+}
+
+impl FakeKitty {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a `get-text` reply.
+    pub fn with_text(self, text: &str) -> Self {
+        self.lock().texts.push_back(text.to_string());
+        self
+    }
+
+    /// Sets the `get-text` reply for commands matching `id:<window_id>`.
+    pub fn with_window_text(self, window_id: u32, text: &str) -> Self {
+        self.lock().window_texts.insert(window_id, text.to_string());
+        self
+    }
+
+    /// Sets the window tree returned by `ls`.
+    pub fn with_windows(self, windows: Value) -> Self {
+        self.lock().windows = windows;
+        self
+    }
+
+    /// Makes every call to `cmd` fail with `error`, as kitty would report it.
+    pub fn with_failure(self, cmd: &str, error: &str) -> Self {
+        self.lock()
+            .failures
+            .insert(cmd.to_string(), error.to_string());
+        self
+    }
+
+    /// Never answers the next `times` calls to `cmd`, like a busy kitty.
+    pub fn with_stall(self, cmd: &str, times: usize) -> Self {
+        self.lock().stalls.insert(cmd.to_string(), times);
+        self
+    }
+
+    /// All commands received so far, in order.
+    pub fn calls(&self) -> Vec<KittyCommand> {
+        self.lock().calls.clone()
+    }
+
+    /// Commands received so far with the given name.
+    pub fn calls_to(&self, cmd: &str) -> Vec<KittyCommand> {
+        self.lock()
+            .calls
+            .iter()
+            .filter(|call| call.cmd == cmd)
+            .cloned()
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn respond(&self, command: &KittyCommand) -> RcResponse {
+        let mut state = self.lock();
+        state.calls.push(command.clone());
+
+        if let Some(error) = state.failures.get(&command.cmd) {
+            return RcResponse {
+                ok: false,
+                data: None,
+                error: Some(error.clone()),
+            };
+        }
+
+        let data = match command.cmd.as_str() {
+            "get-text" => {
+                let window_id = command.to_rc_message()["payload"]["match"]
+                    .as_str()
+                    .and_then(|spec| spec.strip_prefix("id:"))
+                    .and_then(|id| id.parse().ok());
+                let text = if let Some(text) = window_id.and_then(|id| state.window_texts.get(&id)) {
+                    Some(text.clone())
+                } else if state.texts.len() > 1 {
+                    state.texts.pop_front()
+                } else {
+                    state.texts.front().cloned()
+                };
+                Some(json!(text.unwrap_or_default()))
+            }
+            // kitty serialises the window tree as a JSON string
+            "ls" => match &state.windows {
+                Value::Null => Some(json!("[]")),
+                windows => Some(json!(windows.to_string())),
+            },
+            _ => None,
+        };
+
+        RcResponse {
+            ok: true,
+            data,
+            error: None,
+        }
+    }
+}
+
+impl KittyTransport for FakeKitty {
+    fn send<'a>(&'a self, command: &'a KittyCommand) -> BoxFuture<'a, Result<RcResponse>> {
+        let stalled = {
+            let mut state = self.lock();
+            match state.stalls.get_mut(&command.cmd) {
+                Some(left) if *left > 0 => {
+                    *left -= 1;
+                    state.calls.push(command.clone());
+                    true
+                }
+                _ => false,
+            }
+        };
+        if stalled {
+            return Box::pin(futures::future::pending());
+        }
+        let response = self.respond(command);
+        Box::pin(async move { Ok(response) })
+    }
+}
@@ -0,0 +1,275 @@
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Marker group highlighting every match, drawn with kitty's `mark1` colours.
+pub const ALL_MATCHES_GROUP: u8 = 1;
+/// Marker group highlighting the selected match, drawn with `mark2` colours.
+pub const CURRENT_MATCH_GROUP: u8 = 2;
+
+/// kitty has three marker groups, `mark1` to `mark3`.
+pub const MARKER_GROUPS: [u8; 3] = [1, 2, 3];
+
+/// Characters of line context kept around the current match. kitty applies
+/// markers to each screen row, so longer context would stop matching when
+/// a line wraps near the match.
+const CURRENT_MATCH_CONTEXT: usize = 16;
+
+/// Expression types accepted by kitty's `create-marker`.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkerKind {
+    Text,
+    IText,
+    Regex,
+    IRegex,
+}
+
+impl MarkerKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MarkerKind::Text => "text",
+            MarkerKind::IText => "itext",
+            MarkerKind::Regex => "regex",
+            MarkerKind::IRegex => "iregex",
+        }
+    }
+}
+
+/// A query pinned to a marker group, highlighted until it is cleared.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinnedTerm {
+    pub group: u8,
+    pub query: String,
+    pub case_sensitive: bool,
+    pub regex: bool,
+}
+
+impl PinnedTerm {
+    /// Whether both pin the same query with the same search settings,
+    /// whatever their group.
+    pub fn same_term(&self, other: &PinnedTerm) -> bool {
+        self.query == other.query
+            && self.case_sensitive == other.case_sensitive
+            && self.regex == other.regex
+    }
+
+    fn expression(&self) -> String {
+        if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        }
+    }
+}
+
+/// A kitty marker: one expression type and a list of `(group, expression)`
+/// pairs. kitty tries the expressions in order, so earlier groups win where
+/// several match the same text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkerSpec {
+    pub kind: MarkerKind,
+    pub groups: Vec<(u8, String)>,
+}
+
+impl MarkerSpec {
+    pub fn new(kind: MarkerKind) -> Self {
+        Self {
+            kind,
+            groups: Vec::new(),
+        }
+    }
+
+    pub fn group(mut self, group: u8, expression: impl Into<String>) -> Self {
+        self.groups.push((group, expression.into()));
+        self
+    }
+
+    /// Highlights every match of `query` as the search engine finds them:
+    /// as a regex or a literal, with or without case.
+    pub fn for_search(query: &str, case_sensitive: bool, regex: bool) -> Self {
+        let kind = if case_sensitive {
+            MarkerKind::Regex
+        } else {
+            MarkerKind::IRegex
+        };
+        let expression = if regex {
+            query.to_string()
+        } else {
+            regex::escape(query)
+        };
+        Self::new(kind).group(ALL_MATCHES_GROUP, expression)
+    }
+
+    /// Adds the current match, bytes `range` of `line`, in its own group
+    /// ahead of the others. It is told apart from identical matches by the
+    /// text around it on the line.
+    pub fn with_current(mut self, line: &str, range: Range<usize>) -> Self {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (Some(before), Some(matched), Some(after)) = (
+            line.get(..range.start),
+            line.get(range.clone()),
+            line.get(range.end..),
+        ) else {
+            return self;
+        };
+        if matched.is_empty() {
+            return self;
+        }
+
+        let prefix: String = {
+            let chars: Vec<char> = before.chars().rev().take(CURRENT_MATCH_CONTEXT).collect();
+            chars.into_iter().rev().collect()
+        };
+        let suffix: String = after.chars().take(CURRENT_MATCH_CONTEXT).collect();
+
+        let mut expression = String::new();
+        if !prefix.is_empty() {
+            expression.push_str(&format!("(?<={})", regex::escape(&prefix)));
+        }
+        expression.push_str(&regex::escape(matched));
+        if !suffix.is_empty() {
+            expression.push_str(&format!("(?={})", regex::escape(&suffix)));
+        }
+
+        self.groups.insert(0, (CURRENT_MATCH_GROUP, expression));
+        self
+    }
+
+    /// Highlights only the pinned terms.
+    pub fn for_pins(pins: &[PinnedTerm]) -> Self {
+        Self::new(MarkerKind::Regex).with_pins(pins)
+    }
+
+    /// Adds `pins` after the existing groups. kitty applies one case mode
+    /// to a whole marker, so when they differ, case-insensitive expressions
+    /// are wrapped in `(?i:...)` under a case-sensitive `regex` marker.
+    pub fn with_pins(mut self, pins: &[PinnedTerm]) -> Self {
+        for pin in pins {
+            self.push_term(pin.group, pin.expression(), pin.case_sensitive);
+        }
+        self
+    }
+
+    fn push_term(&mut self, group: u8, expression: String, case_sensitive: bool) {
+        self.convert_to_regex();
+        let expression = match (self.kind, case_sensitive) {
+            (MarkerKind::IRegex, true) => {
+                for (_, existing) in &mut self.groups {
+                    *existing = format!("(?i:{})", existing);
+                }
+                self.kind = MarkerKind::Regex;
+                expression
+            }
+            (MarkerKind::Regex, false) if self.groups.is_empty() => {
+                self.kind = MarkerKind::IRegex;
+                expression
+            }
+            (MarkerKind::Regex, false) => format!("(?i:{})", expression),
+            _ => expression,
+        };
+        self.groups.push((group, expression));
+    }
+
+    /// Turns a text marker into the equivalent regex one.
+    fn convert_to_regex(&mut self) {
+        let kind = match self.kind {
+            MarkerKind::Text => MarkerKind::Regex,
+            MarkerKind::IText => MarkerKind::IRegex,
+            _ => return,
+        };
+        for (_, expression) in &mut self.groups {
+            *expression = regex::escape(expression);
+        }
+        self.kind = kind;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Arguments to `kitty @ create-marker`, e.g. `iregex 1 error`.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![self.kind.as_str().to_string()];
+        for (group, expression) in &self.groups {
+            args.push(group.to_string());
+            args.push(expression.clone());
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_settings_pick_marker_type() {
+        assert_eq!(
+            MarkerSpec::for_search("a.b", false, false).to_args(),
+            vec!["iregex", "1", "a\\.b"]
+        );
+        assert_eq!(
+            MarkerSpec::for_search("a.b", true, true).to_args(),
+            vec!["regex", "1", "a.b"]
+        );
+    }
+
+    #[test]
+    fn test_current_match_comes_first_with_context() {
+        let line = "ERROR: disk (sda) full, ERROR again\n";
+        let spec = MarkerSpec::for_search("error", false, false).with_current(line, 24..29);
+        assert_eq!(
+            spec.to_args(),
+            vec![
+                "iregex",
+                "2",
+                "(?<=isk \\(sda\\) full, )ERROR(?= again)",
+                "1",
+                "error",
+            ]
+        );
+    }
+
+    fn pin(group: u8, query: &str, case_sensitive: bool) -> PinnedTerm {
+        PinnedTerm {
+            group,
+            query: query.to_string(),
+            case_sensitive,
+            regex: false,
+        }
+    }
+
+    #[test]
+    fn test_pins_follow_search_groups() {
+        let spec = MarkerSpec::for_search("error", false, false)
+            .with_pins(&[pin(2, "req-42", false), pin(3, "db.local", false)]);
+        assert_eq!(
+            spec.to_args(),
+            vec!["iregex", "1", "error", "2", "req\\-42", "3", "db\\.local"]
+        );
+    }
+
+    #[test]
+    fn test_mixed_case_modes_share_one_marker() {
+        let spec = MarkerSpec::for_pins(&[pin(1, "ERROR", true), pin(2, "host", false)]);
+        assert_eq!(spec.to_args(), vec!["regex", "1", "ERROR", "2", "(?i:host)"]);
+
+        let spec = MarkerSpec::for_search("warn", false, false).with_pins(&[pin(3, "ID", true)]);
+        assert_eq!(spec.to_args(), vec!["regex", "1", "(?i:warn)", "3", "ID"]);
+
+        let spec = MarkerSpec::new(MarkerKind::IText)
+            .group(1, "a.b")
+            .with_pins(&[pin(2, "x", false)]);
+        assert_eq!(spec.to_args(), vec!["iregex", "1", "a\\.b", "2", "x"]);
+    }
+
+    #[test]
+    fn test_current_match_at_line_edges() {
+        let spec = MarkerSpec::new(MarkerKind::Regex).with_current("needle", 0..6);
+        assert_eq!(spec.to_args(), vec!["regex", "2", "needle"]);
+
+        // Out of range or not on a char boundary: left out
+        let spec = MarkerSpec::new(MarkerKind::Regex).with_current("né", 2..3);
+        assert!(spec.groups.is_empty());
+    }
+}
@@ -1,5 +1,18 @@
+pub mod auth;
+pub mod capabilities;
 pub mod client;
 pub mod buffer;
 pub mod commands;
+pub mod discovery;
+pub mod doctor;
+pub mod error;
+#[allow(dead_code)]
+pub mod fake;
+pub mod marker;
+pub mod protocol;
+pub mod response;
+pub mod scroll;
+pub mod transport;
+pub mod windows;
 
 pub use client::KittyClient;
\ No newline at end of file
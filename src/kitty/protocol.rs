@@ -0,0 +1,89 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::kitty::error::KittyError;
+
+/// Remote-control protocol version we advertise to kitty.
+pub const RC_PROTOCOL_VERSION: [u32; 3] = [0, 26, 0];
+
+const ENVELOPE_START: &[u8] = b"\x1bP@kitty-cmd";
+const ENVELOPE_END: &[u8] = b"\x1b\\";
+
+/// Reply sent back by kitty for every remote-control command.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RcResponse {
+    pub ok: bool,
+    #[serde(default)]
+    pub data: Option<Value>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Wraps a JSON message in the DCS `@kitty-cmd` envelope kitty expects.
+pub fn encode_message(message: &Value) -> Vec<u8> {
+    let body = message.to_string();
+    let mut out = Vec::with_capacity(ENVELOPE_START.len() + body.len() + ENVELOPE_END.len());
+    out.extend_from_slice(ENVELOPE_START);
+    out.extend_from_slice(body.as_bytes());
+    out.extend_from_slice(ENVELOPE_END);
+    out
+}
+
+/// Returns the position just past the first complete envelope in `buf`.
+pub fn envelope_end(buf: &[u8]) -> Option<usize> {
+    let start = find(buf, ENVELOPE_START)?;
+    let body = start + ENVELOPE_START.len();
+    find(&buf[body..], ENVELOPE_END).map(|end| body + end + ENVELOPE_END.len())
+}
+
+/// Extracts and parses the first complete reply envelope in `buf`.
+pub fn decode_response(buf: &[u8]) -> Result<RcResponse> {
+    let start = find(buf, ENVELOPE_START)
+        .ok_or_else(|| KittyError::malformed("no kitty-cmd envelope in response"))?;
+    let body = &buf[start + ENVELOPE_START.len()..];
+    let end = find(body, ENVELOPE_END)
+        .ok_or_else(|| KittyError::malformed("unterminated kitty-cmd envelope in response"))?;
+    Ok(serde_json::from_slice(&body[..end]).map_err(KittyError::malformed)?)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_wraps_message() {
+        let encoded = encode_message(&json!({"cmd": "ls"}));
+        assert_eq!(encoded, b"\x1bP@kitty-cmd{\"cmd\":\"ls\"}\x1b\\".to_vec());
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let raw = b"noise\x1bP@kitty-cmd{\"ok\": true, \"data\": \"hello\"}\x1b\\";
+        assert_eq!(envelope_end(raw), Some(raw.len()));
+
+        let response = decode_response(raw).unwrap();
+        assert!(response.ok);
+        assert_eq!(response.data, Some(json!("hello")));
+    }
+
+    #[test]
+    fn test_decode_error_response() {
+        let raw = b"\x1bP@kitty-cmd{\"ok\": false, \"error\": \"No matching windows\"}\x1b\\";
+        let response = decode_response(raw).unwrap();
+        assert!(!response.ok);
+        assert_eq!(response.error.as_deref(), Some("No matching windows"));
+    }
+
+    #[test]
+    fn test_incomplete_envelope() {
+        let raw = b"\x1bP@kitty-cmd{\"ok\": true";
+        assert_eq!(envelope_end(raw), None);
+        assert!(decode_response(raw).is_err());
+    }
+}
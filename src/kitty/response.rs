@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::kitty::error::KittyError;
+use crate::kitty::windows::WindowTree;
+
+/// The decoded reply to a successful `KittyCommand`, typed by command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// Commands that only report success, such as `scroll-window`
+    Done,
+    /// `get-text`
+    Text(String),
+    /// `ls`
+    Windows(WindowTree),
+    /// `get-colors`, as colour name to value
+    Colors(BTreeMap<String, String>),
+    /// `launch`: the id of the new window, when kitty reports it
+    Launched(Option<u32>),
+    /// Commands without a typed reply
+    Other(Value),
+}
+
+impl Response {
+    /// Decodes the `data` kitty sent back for `cmd`.
+    pub fn parse(cmd: &str, data: Value) -> Result<Self> {
+        Ok(match cmd {
+            "get-text" => match data {
+                Value::String(text) => Response::Text(text),
+                other => return Err(KittyError::malformed(format!("unexpected get-text data: {}", other)).into()),
+            },
+            // kitty returns the window tree as a JSON-encoded string
+            "ls" => {
+                let tree = match data {
+                    Value::String(json_str) => serde_json::from_str(&json_str),
+                    other => serde_json::from_value(other),
+                };
+                Response::Windows(tree.map_err(|e| KittyError::malformed(format!("unexpected ls data: {}", e)))?)
+            }
+            "get-colors" => match data {
+                Value::String(text) => Response::Colors(parse_colors(&text)),
+                other => return Err(KittyError::malformed(format!("unexpected get-colors data: {}", other)).into()),
+            },
+            "launch" => Response::Launched(match data {
+                Value::Number(id) => id.as_u64().map(|id| id as u32),
+                Value::String(id) => id.trim().parse().ok(),
+                _ => None,
+            }),
+            "action" | "create-marker" | "focus-window" | "remove-marker" | "resize-window"
+            | "scroll-window" | "send-text" | "set-colors" | "set-window-title" => Response::Done,
+            _ => Response::Other(data),
+        })
+    }
+
+    pub fn into_text(self) -> Result<String> {
+        match self {
+            Response::Text(text) => Ok(text),
+            other => Err(anyhow!("Expected text from kitty, got {:?}", other)),
+        }
+    }
+
+    pub fn into_windows(self) -> Result<WindowTree> {
+        match self {
+            Response::Windows(tree) => Ok(tree),
+            other => Err(anyhow!("Expected a window list from kitty, got {:?}", other)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn into_colors(self) -> Result<BTreeMap<String, String>> {
+        match self {
+            Response::Colors(colors) => Ok(colors),
+            other => Err(anyhow!("Expected colours from kitty, got {:?}", other)),
+        }
+    }
+
+    pub fn into_window_id(self) -> Result<Option<u32>> {
+        match self {
+            Response::Launched(id) => Ok(id),
+            other => Err(anyhow!("Expected a window id from kitty, got {:?}", other)),
+        }
+    }
+}
+
+/// Parses `get-colors` output: one `name value` pair per line.
+fn parse_colors(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parses_typed_replies() {
+        assert_eq!(
+            Response::parse("get-text", json!("hello")).unwrap(),
+            Response::Text("hello".to_string())
+        );
+        assert_eq!(Response::parse("launch", json!("42")).unwrap(), Response::Launched(Some(42)));
+        assert_eq!(Response::parse("scroll-window", Value::Null).unwrap(), Response::Done);
+
+        let tree = Response::parse("ls", json!(r#"[{"id": 1, "tabs": []}]"#))
+            .unwrap()
+            .into_windows()
+            .unwrap();
+        assert_eq!(tree.os_windows[0].id, 1);
+    }
+
+    #[test]
+    fn test_parses_colors() {
+        let colors = Response::parse("get-colors", json!("background   #000000\nforeground #dddddd\n"))
+            .unwrap()
+            .into_colors()
+            .unwrap();
+        assert_eq!(colors["background"], "#000000");
+        assert_eq!(colors["foreground"], "#dddddd");
+    }
+
+    #[test]
+    fn test_mismatched_replies_are_errors() {
+        assert!(Response::parse("get-text", Value::Null).is_err());
+        assert!(Response::Done.into_text().is_err());
+    }
+}
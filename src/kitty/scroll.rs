@@ -0,0 +1,216 @@
+use crate::kitty::commands::Extent;
+use crate::search::ansi;
+use crate::search::blocks::{self, CommandBlock};
+
+/// Where in the viewport a jumped-to match ends up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum JumpPosition {
+    Top,
+    #[default]
+    Center,
+    Bottom,
+}
+
+/// A match to scroll to, described relative to the capture it was found in.
+#[derive(Debug, Clone)]
+pub struct JumpTarget<'a> {
+    /// Extent the searched text was captured with
+    pub extent: Extent,
+    /// Number of lines in that capture
+    pub captured_lines: usize,
+    /// 1-based line number of the match within the capture
+    pub line_number: u64,
+    /// Text of the matching line, used to find it again if the scrollback moved
+    pub line: &'a str,
+    /// Rows the window was scrolled up by when the capture was taken; a
+    /// capture of the screen ends that far above the bottom
+    pub scrolled_by: usize,
+}
+
+/// Maps logical lines of a `get-text --extent=all --add-wrap-markers` capture
+/// to kitty's screen rows, which is what `scroll-window` counts in.
+///
+/// kitty marks each soft wrap with `\r`, so a logical line containing `n`
+/// wrap markers spans `n + 1` rows.
+#[derive(Debug)]
+pub struct ScrollbackMap {
+    lines: Vec<String>,
+    row_starts: Vec<usize>,
+    total_rows: usize,
+    /// Only known when parsed from an `--ansi` capture, see `parse_ansi`
+    blocks: Vec<CommandBlock>,
+}
+
+impl ScrollbackMap {
+    pub fn parse(wrapped: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut row_starts = Vec::new();
+        let mut row = 0;
+
+        for raw in wrapped.lines() {
+            row_starts.push(row);
+            row += 1 + raw.matches('\r').count();
+            lines.push(raw.replace('\r', ""));
+        }
+
+        Self {
+            lines,
+            row_starts,
+            total_rows: row,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Like `parse`, for a capture that also has `--ansi`, keeping its shell
+    /// integration marks so command output can be found again.
+    pub fn parse_ansi(wrapped: &str) -> Self {
+        let styled = ansi::parse(wrapped);
+        Self {
+            blocks: blocks::segment(&styled),
+            ..Self::parse(&styled.text)
+        }
+    }
+
+    /// Finds the 0-based logical line `target` refers to in this (possibly
+    /// newer) capture.
+    ///
+    /// Captures of the whole scrollback are anchored at the top and screen
+    /// captures at the rows the window showed. Command output is anchored
+    /// at its output mark: the last command's, or for the last visited one,
+    /// the latest output of the captured length with the matching line
+    /// there. Anything else is assumed to end at the bottom. If the
+    /// scrollback has grown or been trimmed since, the nearest line with the
+    /// same text is used.
+    pub fn locate(&self, target: &JumpTarget) -> Option<usize> {
+        let wanted = target.line.trim_end_matches(['\r', '\n']);
+        let from_start = (target.line_number as usize).checked_sub(1)?;
+        let matches = |idx: usize| self.lines.get(idx).is_some_and(|line| line == wanted);
+
+        let from_end = target.captured_lines.saturating_sub(from_start);
+        let from_bottom = |end: usize| end.saturating_sub(from_end);
+        let outputs = self.blocks.iter().rev().filter(|block| !block.output_lines.is_empty());
+        let output_start = |block: &CommandBlock| block.output_lines.start as usize - 1 + from_start;
+
+        let expected = match target.extent {
+            Extent::All => Some(from_start),
+            Extent::Screen => self
+                .total_rows
+                .checked_sub(target.scrolled_by + 1)
+                .map(|last_row| from_bottom(self.row_starts.partition_point(|&start| start <= last_row))),
+            Extent::LastCmdOutput => outputs.map(output_start).next(),
+            Extent::LastVisitedCmdOutput => outputs
+                .filter(|block| block.output_lines.end - block.output_lines.start == target.captured_lines as u64)
+                .map(output_start)
+                .find(|&idx| matches(idx)),
+            Extent::Selection => None,
+        }
+        .unwrap_or_else(|| from_bottom(self.lines.len()));
+
+        (0..=self.lines.len()).find_map(|distance| {
+            [expected.checked_sub(distance), expected.checked_add(distance)]
+                .into_iter()
+                .flatten()
+                .find(|&idx| matches(idx))
+        })
+    }
+
+    /// First screen row of logical line `idx`.
+    pub fn row_of(&self, idx: usize) -> Option<usize> {
+        self.row_starts.get(idx).copied()
+    }
+
+    /// Row to scroll to the top of a `viewport_rows` high window so that
+    /// `row` is shown at `position`.
+    pub fn top_row_for(&self, row: usize, viewport_rows: usize, position: JumpPosition) -> usize {
+        let offset = match position {
+            JumpPosition::Top => 0,
+            JumpPosition::Center => viewport_rows / 2,
+            JumpPosition::Bottom => viewport_rows.saturating_sub(1),
+        };
+        let max_top = self.total_rows.saturating_sub(viewport_rows);
+        row.saturating_sub(offset).min(max_top)
+    }
+
+    /// How many rows a `viewport_rows` high window showing `top_row` at the
+    /// top is scrolled up by.
+    pub fn scrolled_by_at(&self, top_row: usize, viewport_rows: usize) -> usize {
+        self.total_rows.saturating_sub(viewport_rows).saturating_sub(top_row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(extent: Extent, captured_lines: usize, line_number: u64, line: &str) -> JumpTarget<'_> {
+        JumpTarget {
+            extent,
+            captured_lines,
+            line_number,
+            line,
+            scrolled_by: 0,
+        }
+    }
+
+    #[test]
+    fn test_wrapped_lines_take_several_rows() {
+        let map = ScrollbackMap::parse("short\na very long li\rne that wraps\rtwice\nend\n");
+        assert_eq!(map.total_rows, 5);
+        assert_eq!(map.row_of(0), Some(0));
+        assert_eq!(map.row_of(1), Some(1));
+        assert_eq!(map.row_of(2), Some(4));
+    }
+
+    #[test]
+    fn test_locate_after_scrollback_grew() {
+        let map = ScrollbackMap::parse("a\nb\nmatch\nc\nd\ne\n");
+        assert_eq!(map.locate(&target(Extent::All, 4, 3, "match\n")), Some(2));
+
+        // Captured from the screen: line 1 of a 2-line capture, with two
+        // lines printed since
+        assert_eq!(map.locate(&target(Extent::Screen, 2, 1, "match\n")), Some(2));
+    }
+
+    #[test]
+    fn test_locate_after_scrollback_trimmed() {
+        // Two lines dropped off the top since the capture
+        let map = ScrollbackMap::parse("match\nc\nd\ne\n");
+        assert_eq!(map.locate(&target(Extent::All, 4, 3, "match")), Some(0));
+        assert_eq!(map.locate(&target(Extent::All, 4, 3, "gone")), None);
+    }
+
+    #[test]
+    fn test_locate_captures_not_at_the_bottom() {
+        // The window was scrolled up 3 rows, so the 3-line screen capture
+        // was "ERROR", "x\r5" and "x6", not the last three lines
+        let map = ScrollbackMap::parse("x0\nERROR\nx2\nx3\nERROR\nx\r5\nx6\nERROR\nx8\nx9\n");
+        let scrolled = JumpTarget {
+            scrolled_by: 3,
+            ..target(Extent::Screen, 3, 1, "ERROR")
+        };
+        assert_eq!(map.locate(&scrolled), Some(4));
+
+        // Command output is followed by a two-line prompt
+        let prompt = "\x1b]133;A\x07~/src\n$ \x1b]133;B\x07make\n\x1b]133;C\x07";
+        let map = ScrollbackMap::parse_ansi(&format!(
+            "{prompt}ERROR\na\nb\n{prompt}ERROR\na\nb\nERROR\n\x1b]133;A\x07~/src\n$ \n"
+        ));
+        assert_eq!(map.locate(&target(Extent::LastCmdOutput, 4, 1, "ERROR")), Some(7));
+        assert_eq!(map.locate(&target(Extent::LastCmdOutput, 4, 4, "ERROR")), Some(10));
+        assert_eq!(map.locate(&target(Extent::LastVisitedCmdOutput, 3, 1, "ERROR")), Some(2));
+    }
+
+    #[test]
+    fn test_top_row_centres_and_clamps() {
+        let text: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+        let map = ScrollbackMap::parse(&text);
+
+        assert_eq!(map.top_row_for(50, 20, JumpPosition::Center), 40);
+        assert_eq!(map.top_row_for(50, 20, JumpPosition::Top), 50);
+        assert_eq!(map.top_row_for(50, 20, JumpPosition::Bottom), 31);
+        assert_eq!(map.top_row_for(3, 20, JumpPosition::Center), 0);
+        assert_eq!(map.top_row_for(99, 20, JumpPosition::Top), 80);
+        assert_eq!(map.scrolled_by_at(40, 20), 40);
+        assert_eq!(map.scrolled_by_at(80, 20), 0);
+    }
+}
@@ -0,0 +1,406 @@
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
+use std::fmt;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+use crate::kitty::auth::CommandEncrypter;
+use crate::kitty::commands::KittyCommand;
+use crate::kitty::error::KittyError;
+use crate::kitty::protocol::{self, RcResponse};
+
+/// Where remote-control messages are delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A `unix:` socket path from `listen_on`.
+    Unix(PathBuf),
+    /// A Linux abstract socket, written as `unix:@name`.
+    Abstract(String),
+    /// A `tcp:host:port` listener.
+    Tcp(String),
+    /// The controlling terminal, used when running inside a kitty window.
+    Tty,
+}
+
+impl Endpoint {
+    /// Parses a `KITTY_LISTEN_ON` style address.
+    pub fn parse(spec: &str) -> Result<Self> {
+        if let Some(path) = spec.strip_prefix("unix:") {
+            match path.strip_prefix('@') {
+                Some(name) => Ok(Endpoint::Abstract(name.to_string())),
+                None => Ok(Endpoint::Unix(PathBuf::from(path))),
+            }
+        } else if let Some(addr) = spec.strip_prefix("tcp:") {
+            Ok(Endpoint::Tcp(addr.to_string()))
+        } else {
+            Err(anyhow!("Unsupported kitty socket address: {}", spec))
+        }
+    }
+}
+
+/// Writes the address back in `KITTY_LISTEN_ON` form.
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+            Endpoint::Abstract(name) => write!(f, "unix:@{}", name),
+            Endpoint::Tcp(addr) => write!(f, "tcp:{}", addr),
+            Endpoint::Tty => write!(f, "tty"),
+        }
+    }
+}
+
+/// Delivers a remote-control command to kitty and returns its reply.
+///
+/// `KittyClient` only talks to kitty through this trait, so tests can swap
+/// in [`crate::kitty::fake::FakeKitty`] instead of a live terminal.
+pub trait KittyTransport: Send + Sync {
+    fn send<'a>(&'a self, command: &'a KittyCommand) -> BoxFuture<'a, Result<RcResponse>>;
+}
+
+/// Hands out the controlling terminal, which carries both the user's keys
+/// and kitty's replies to commands sent over it.
+///
+/// Whoever reads it while a reply is on its way gets that reply, so command
+/// exchanges and input polling take turns. Exchanges go first: while one is
+/// waiting or running, input polling stands aside, otherwise crossterm
+/// would read the reply as keystrokes and the exchange would time out.
+#[derive(Default)]
+pub struct TtyGate {
+    lock: Mutex<()>,
+    waiting: AtomicUsize,
+}
+
+static TTY_GATE: TtyGate = TtyGate::new();
+
+impl TtyGate {
+    pub const fn new() -> Self {
+        Self {
+            lock: Mutex::new(()),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// The gate for this process's terminal.
+    pub fn get() -> &'static TtyGate {
+        &TTY_GATE
+    }
+
+    /// Takes the terminal for a command exchange, once any input poll in
+    /// progress has finished.
+    pub fn exchange(&self) -> MutexGuard<'_, ()> {
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let turn = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        turn
+    }
+
+    /// Takes the terminal for reading input, unless an exchange wants it.
+    pub fn input(&self) -> Option<MutexGuard<'_, ()>> {
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            return None;
+        }
+        match self.lock.try_lock() {
+            Ok(turn) => Some(turn),
+            Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+}
+
+/// Speaks kitty's JSON remote-control protocol directly, without spawning
+/// `kitty @` for each call.
+pub struct NativeTransport {
+    endpoint: Endpoint,
+    encrypter: Option<CommandEncrypter>,
+    /// How long to wait for kitty's reply on the terminal. Socket exchanges
+    /// are simply dropped when the client gives up, but a blocking read
+    /// would keep the terminal locked.
+    tty_timeout: Duration,
+}
+
+impl KittyTransport for NativeTransport {
+    fn send<'a>(&'a self, command: &'a KittyCommand) -> BoxFuture<'a, Result<RcResponse>> {
+        Box::pin(self.exchange(command))
+    }
+}
+
+impl NativeTransport {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            encrypter: None,
+            tty_timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.tty_timeout = timeout;
+        self
+    }
+
+    /// Encrypts every command, for kitty instances using `remote_control_password`.
+    pub fn with_encrypter(mut self, encrypter: CommandEncrypter) -> Self {
+        self.encrypter = Some(encrypter);
+        self
+    }
+
+    async fn exchange(&self, command: &KittyCommand) -> Result<RcResponse> {
+        let mut message = command.to_rc_message();
+        if let Some(encrypter) = &self.encrypter {
+            message = encrypter.encrypt(message)?;
+        }
+        let request = protocol::encode_message(&message);
+
+        match &self.endpoint {
+            Endpoint::Unix(path) => {
+                let stream = UnixStream::connect(path).await?;
+                exchange(stream, &request).await
+            }
+            Endpoint::Abstract(name) => {
+                let stream = connect_abstract(name)?;
+                exchange(stream, &request).await
+            }
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).await?;
+                exchange(stream, &request).await
+            }
+            Endpoint::Tty => {
+                // Sockets get a connection per command, but the terminal is
+                // shared with other commands and with the UI's input
+                let (cmd, timeout) = (command.cmd.clone(), self.tty_timeout);
+                tokio::task::spawn_blocking(move || {
+                    let _turn = TtyGate::get().exchange();
+                    exchange_tty(&request, &cmd, timeout)
+                })
+                .await?
+            }
+        }
+    }
+}
+
+async fn exchange<S>(mut stream: S, request: &[u8]) -> Result<RcResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(request).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&chunk[..n]);
+        if protocol::envelope_end(&response).is_some() {
+            break;
+        }
+    }
+
+    protocol::decode_response(&response)
+}
+
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &str) -> Result<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixStream as StdUnixStream};
+
+    let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+    let stream = StdUnixStream::connect_addr(&addr)?;
+    stream.set_nonblocking(true)?;
+    Ok(UnixStream::from_std(stream)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(name: &str) -> Result<UnixStream> {
+    Err(anyhow!("Abstract sockets are not supported on this platform: @{}", name))
+}
+
+/// Sends a request over the controlling terminal and reads kitty's reply
+/// from the same device.
+fn exchange_tty(request: &[u8], cmd: &str, timeout: Duration) -> Result<RcResponse> {
+    let response = tty_round_trip(request, cmd, timeout, |response| {
+        protocol::envelope_end(response).is_some()
+    })?;
+    protocol::decode_response(&response)
+}
+
+/// Writes `request` to the terminal and reads the terminal's answer, such as
+/// a reply to an escape sequence, until `complete` accepts it.
+pub async fn query_terminal(
+    request: &'static [u8],
+    timeout: Duration,
+    complete: fn(&[u8]) -> bool,
+) -> Result<Vec<u8>> {
+    tokio::task::spawn_blocking(move || {
+        let _turn = TtyGate::get().exchange();
+        tty_round_trip(request, "terminal query", timeout, complete)
+    })
+    .await?
+}
+
+/// The terminal must be in raw mode while waiting, otherwise the line
+/// discipline holds the reply back and echoes it.
+fn tty_round_trip(
+    request: &[u8],
+    cmd: &str,
+    timeout: Duration,
+    complete: impl Fn(&[u8]) -> bool,
+) -> Result<Vec<u8>> {
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+
+    let was_raw = crossterm::terminal::is_raw_mode_enabled()?;
+    if !was_raw {
+        crossterm::terminal::enable_raw_mode()?;
+    }
+
+    let result = (|| {
+        tty.write_all(request)?;
+        tty.flush()?;
+
+        let deadline = Instant::now() + timeout;
+        let mut response = Vec::new();
+        let mut chunk = [0u8; 8192];
+        while !complete(&response) {
+            if !wait_readable(&tty, deadline.saturating_duration_since(Instant::now()))? {
+                return Err(KittyError::Timeout {
+                    command: cmd.to_string(),
+                    timeout,
+                }
+                .into());
+            }
+            let n = tty.read(&mut chunk)?;
+            if n == 0 {
+                return Err(anyhow!("Terminal closed before kitty replied"));
+            }
+            response.extend_from_slice(&chunk[..n]);
+        }
+        Ok(response)
+    })();
+
+    if !was_raw {
+        crossterm::terminal::disable_raw_mode()?;
+    }
+
+    result
+}
+
+/// Waits up to `timeout` for `tty` to have input; `false` if it has none.
+fn wait_readable(tty: &std::fs::File, timeout: Duration) -> Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: tty.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = timeout.as_millis().min(i32::MAX as u128) as i32;
+    loop {
+        // SAFETY: `fd` is a single valid pollfd that outlives the call
+        let ready = unsafe { libc::poll(&mut fd, 1, millis) };
+        if ready >= 0 {
+            return Ok(ready > 0);
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kitty::commands::Extent;
+
+    #[test]
+    fn test_parse_endpoints() {
+        assert_eq!(
+            Endpoint::parse("unix:/tmp/kitty-42").unwrap(),
+            Endpoint::Unix(PathBuf::from("/tmp/kitty-42"))
+        );
+        assert_eq!(
+            Endpoint::parse("unix:@mykitty").unwrap(),
+            Endpoint::Abstract("mykitty".to_string())
+        );
+        assert_eq!(
+            Endpoint::parse("tcp:localhost:12345").unwrap(),
+            Endpoint::Tcp("localhost:12345".to_string())
+        );
+        assert!(Endpoint::parse("/tmp/kitty").is_err());
+
+        for address in ["unix:/tmp/kitty-42", "unix:@mykitty", "tcp:localhost:12345"] {
+            assert_eq!(Endpoint::parse(address).unwrap().to_string(), address);
+        }
+    }
+
+    #[test]
+    fn test_input_waits_for_tty_exchanges() {
+        static GATE: TtyGate = TtyGate::new();
+        let turn = GATE.exchange();
+        assert!(GATE.input().is_none());
+        drop(turn);
+
+        // An exchange waiting for its turn keeps further input polls away
+        let input = GATE.input().unwrap();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let exchange = std::thread::spawn(move || {
+            let _turn = GATE.exchange();
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        while GATE.waiting.load(Ordering::SeqCst) == 0 {
+            std::thread::yield_now();
+        }
+        drop(input);
+        assert!(GATE.input().is_none());
+
+        started_rx.recv().unwrap();
+        assert!(GATE.input().is_none());
+        release_tx.send(()).unwrap();
+        exchange.join().unwrap();
+        assert!(GATE.input().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kitty.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0u8; 1024];
+            while protocol::envelope_end(&request).is_none() {
+                let n = stream.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..n]);
+            }
+            stream
+                .write_all(b"\x1bP@kitty-cmd{\"ok\": true, \"data\": \"line one\\nline two\"}\x1b\\")
+                .await
+                .unwrap();
+            request
+        });
+
+        let transport = NativeTransport::new(Endpoint::Unix(path));
+        let response = transport.send(&KittyCommand::get_text(Extent::All)).await.unwrap();
+        assert!(response.ok);
+        assert_eq!(response.data.unwrap(), "line one\nline two");
+
+        let request = server.await.unwrap();
+        let body = &request[b"\x1bP@kitty-cmd".len()..request.len() - 2];
+        let message: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(message["cmd"], "get-text");
+    }
+}
@@ -1,3 +1,5 @@
+pub mod config;
+pub mod pins;
 pub mod search;
 pub mod ui;
 pub mod kitty;
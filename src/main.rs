@@ -1,12 +1,28 @@
-use anyhow::Result;
-use clap::Parser;
-use tracing::{info, Level};
-use tracing_subscriber;
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing::{info, warn, Level};
+use tracing_subscriber::fmt::format::FmtSpan;
 
+mod config;
+mod pins;
 mod search;
 mod ui;
 mod kitty;
 
+use config::Config;
+use kitty::auth;
+use kitty::client::DEFAULT_TIMEOUT;
+use kitty::doctor::{self, Environment};
+use kitty::commands::{Extent, LaunchType};
+use kitty::error::hint_for;
+use kitty::scroll::JumpPosition;
+use pins::PinStore;
+use ui::background::SEARCH_DEBOUNCE;
+use search::ansi::StyleFilter;
+use search::blocks::BlockFilter;
 use search::SearchEngine;
 use ui::SearchUI;
 use kitty::KittyClient;
@@ -35,24 +51,297 @@ struct Args {
     /// Use regex patterns
     #[arg(long)]
     regex: bool,
+
+    /// Kitty remote-control address to use, e.g. unix:/tmp/kitty-1234
+    #[arg(long)]
+    to: Option<String>,
+
+    /// Remote-control password (see remote_control_password in kitty.conf)
+    #[arg(long)]
+    password: Option<String>,
+
+    /// Read the remote-control password from this file
+    #[arg(long)]
+    password_file: Option<PathBuf>,
+
+    /// Id of the kitty window to search (default: the window under the overlay)
+    #[arg(long)]
+    window_id: Option<u32>,
+
+    /// Kitty match expression selecting the window to search, e.g. title:logs
+    #[arg(long = "match", value_name = "SPEC", conflicts_with = "window_id")]
+    match_spec: Option<String>,
+
+    /// Search every kitty window and tab; picking a result focuses its window
+    #[arg(long, conflicts_with_all = ["window_id", "match_spec", "ansi", "style"])]
+    all_windows: bool,
+
+    /// Which part of the window to search
+    #[arg(long, value_enum, default_value_t = Extent::All)]
+    extent: Extent,
+
+    /// Capture colours too, so matches can be filtered by style
+    #[arg(long)]
+    ansi: bool,
+
+    /// Only match text in this style, e.g. red, fg=196,bold or bg=#ff0000 (implies --ansi)
+    #[arg(long, value_name = "STYLE")]
+    style: Option<String>,
+
+    /// Show which command each match belongs to (needs shell integration)
+    #[arg(long, conflicts_with = "all_windows")]
+    commands: bool,
+
+    /// Only search command lines, command output, or output of failed commands
+    #[arg(long, value_enum, value_name = "PART", conflicts_with = "all_windows")]
+    only: Option<BlockFilter>,
+
+    /// Where to place the selected match in the window when jumping to it
+    #[arg(long, value_enum, default_value_t = JumpPosition::Center)]
+    jump_position: JumpPosition,
+
+    /// Lines the searched window was scrolled up by when the search started,
+    /// restored when the search is abandoned; pass kitty's `@scrolled-by`
+    #[arg(long, default_value_t = 0)]
+    scrolled_by: u32,
+
+    /// Leave pinned terms (Ctrl+P) highlighted after the search closes
+    #[arg(long)]
+    keep_markers: bool,
+
+    /// How long to wait for kitty to answer each command, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = DEFAULT_TIMEOUT.as_millis() as u64)]
+    timeout_ms: u64,
+
+    /// How long the query has to stay the same before it is searched, in
+    /// milliseconds
+    #[arg(long, value_name = "MS", default_value_t = SEARCH_DEBOUNCE.as_millis() as u64)]
+    debounce_ms: u64,
+
+    /// Re-launch inside kitty, searching the current window (for kitty.conf mappings)
+    #[arg(long, value_name = "WHERE")]
+    launch: Option<LaunchType>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check the kitty setup and print what to fix
+    Doctor {
+        /// Also check permission to scroll, focus and launch windows by
+        /// doing so (a background `true` is launched)
+        #[arg(long)]
+        probe_launch: bool,
+    },
+    /// Remove pinned highlights from this window (or --window-id/--match) and forget them
+    ClearMarkers {
+        /// Clear the pins of every window that has some
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+impl Args {
+    /// Arguments for the copy of ourselves started by `--launch`.
+    fn relaunch_args(&self, window_id: Option<u32>) -> Vec<String> {
+        let mut args = vec![
+            "--buffer-size".to_string(),
+            self.buffer_size.to_string(),
+            "--extent".to_string(),
+            self.extent.as_str().to_string(),
+        ];
+        if let Some(id) = window_id {
+            args.extend(["--window-id".to_string(), id.to_string()]);
+        }
+        // The overlay may reach kitty over an inherited socket rather than
+        // its terminal, which needs the same address we used. The password
+        // goes in its environment, where `ps` does not show it.
+        if let Some(to) = &self.to {
+            args.extend(["--to".to_string(), to.clone()]);
+        }
+        if let Some(query) = &self.query {
+            args.extend(["--query".to_string(), query.clone()]);
+        }
+        if self.case_sensitive {
+            args.push("--case-sensitive".to_string());
+        }
+        if self.regex {
+            args.push("--regex".to_string());
+        }
+        if let Some(position) = self.jump_position.to_possible_value() {
+            args.extend(["--jump-position".to_string(), position.get_name().to_string()]);
+        }
+        if self.scrolled_by > 0 {
+            args.extend(["--scrolled-by".to_string(), self.scrolled_by.to_string()]);
+        }
+        if self.all_windows {
+            args.push("--all-windows".to_string());
+        }
+        if self.keep_markers {
+            args.push("--keep-markers".to_string());
+        }
+        if self.ansi {
+            args.push("--ansi".to_string());
+        }
+        if let Some(style) = &self.style {
+            args.extend(["--style".to_string(), style.clone()]);
+        }
+        if self.commands {
+            args.push("--commands".to_string());
+        }
+        if let Some(part) = self.only.and_then(|part| part.to_possible_value()) {
+            args.extend(["--only".to_string(), part.get_name().to_string()]);
+        }
+        if Duration::from_millis(self.timeout_ms) != DEFAULT_TIMEOUT {
+            args.extend(["--timeout-ms".to_string(), self.timeout_ms.to_string()]);
+        }
+        if Duration::from_millis(self.debounce_ms) != SEARCH_DEBOUNCE {
+            args.extend(["--debounce-ms".to_string(), self.debounce_ms.to_string()]);
+        }
+        if self.debug {
+            args.push("--debug".to_string());
+        }
+        args
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+async fn main() -> ExitCode {
+    match run(Args::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            if let Some(hint) = hint_for(&e) {
+                eprintln!("Hint: {}", hint);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<()> {
+    let style_filter = args.style.as_deref().map(StyleFilter::parse).transpose()?;
     
     // Initialize logging
     let log_level = if args.debug { Level::DEBUG } else { Level::INFO };
+    // With --debug, closing a `kitty` span logs how long the command took
     tracing_subscriber::fmt()
         .with_max_level(log_level)
+        .with_span_events(FmtSpan::CLOSE)
         .init();
     
     info!("Starting Kitty Fast Search v{}", env!("CARGO_PKG_VERSION"));
     
     // Initialize components
-    let kitty_client = KittyClient::new().await?;
+    let config = Config::load()?;
+    let password = auth::resolve_password(
+        args.password.clone(),
+        args.password_file.as_deref(),
+        config.password()?,
+    )?;
+    let timeout = Duration::from_millis(args.timeout_ms);
+    let kitty_client = KittyClient::new(args.to.as_deref(), password.as_deref(), timeout).await;
+
+    if let Some(Command::Doctor { probe_launch }) = args.command {
+        let instance = kitty_client.as_ref().ok().and_then(|client| client.instance());
+        let pin_store = PinStore::load()
+            .unwrap_or_else(|e| {
+                warn!("Could not load pinned terms: {}", e);
+                PinStore::default()
+            })
+            .with_instance(instance.map(str::to_string));
+        let report =
+            doctor::diagnose(&Environment::detect().await, &kitty_client, &pin_store, probe_launch).await;
+        println!("{}", report);
+        if !report.passed() {
+            return Err(anyhow!("some checks failed"));
+        }
+        return Ok(());
+    }
+    let kitty_client = kitty_client?;
+
+    if let Some(launch_type) = args.launch {
+        // Record the window we were invoked from, then hand over to a copy of
+        // ourselves running in the new kitty window. Kitty closes that window
+        // when the search UI exits.
+        let current_window = match (args.window_id, &args.match_spec) {
+            (Some(id), _) => Some(id),
+            (None, Some(spec)) => Some(kitty_client.resolve_match(spec).await?),
+            (None, None) => match own_window_id() {
+                Some(id) => Some(id),
+                None => kitty_client.detect_target_window().await?,
+            },
+        };
+
+        let mut command = vec![std::env::current_exe()?.display().to_string()];
+        command.extend(args.relaunch_args(current_window));
+        let env: Vec<(&str, &str)> = password
+            .iter()
+            .map(|password| (auth::PASSWORD_ENV, password.as_str()))
+            .collect();
+        kitty_client.launch(launch_type, current_window, &env, command).await?;
+        return Ok(());
+    }
+
+    if let Some(Command::ClearMarkers { all }) = args.command {
+        let mut pin_store = PinStore::load()?.with_instance(kitty_client.instance().map(str::to_string));
+        let windows = if all {
+            pin_store.clear_all()
+        } else {
+            // Without --window-id or --match, the pins of the window we are
+            // run from, not of the one that was active before it
+            let window = match (args.window_id, &args.match_spec) {
+                (Some(id), _) => Some(id),
+                (None, Some(spec)) => Some(kitty_client.resolve_match(spec).await?),
+                (None, None) => match own_window_id() {
+                    Some(id) => Some(id),
+                    None => kitty_client.list_windows().await?.self_window().map(|window| window.id),
+                },
+            };
+            pin_store.set_pins(window, Vec::new());
+            vec![window]
+        };
+        pin_store.save()?;
+        for window_id in windows {
+            // Windows pinned in an earlier session may be gone by now
+            if let Err(e) = kitty_client.remove_marker(window_id).await {
+                warn!("{}", e);
+            }
+        }
+        return Ok(());
+    }
+
+    let target_window = match (args.window_id, &args.match_spec) {
+        (Some(id), _) => Some(id),
+        (None, Some(spec)) => Some(kitty_client.resolve_match(spec).await?),
+        (None, None) => kitty_client
+            .detect_target_window()
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Could not detect the window to search, using our own: {}", e);
+                None
+            })
+            // Name our own window, so its pins are saved under its id
+            .or_else(own_window_id),
+    };
+    let pin_store = PinStore::load()?.with_instance(kitty_client.instance().map(str::to_string));
+
     let search_engine = SearchEngine::new(args.buffer_size, args.case_sensitive, args.regex)?;
     let mut search_ui = SearchUI::new(kitty_client, search_engine).await?;
+    search_ui.set_target_window(target_window);
+    search_ui.set_extent(args.extent);
+    search_ui.set_ansi(args.ansi);
+    search_ui.set_jump_position(args.jump_position);
+    search_ui.set_scroll_origin(args.scrolled_by);
+    search_ui.set_pin_store(pin_store);
+    search_ui.set_keep_markers(args.keep_markers);
+    search_ui.set_all_windows(args.all_windows);
+    search_ui.set_style_filter(style_filter);
+    search_ui.set_command_blocks(args.commands);
+    search_ui.set_block_filter(args.only);
+    search_ui.set_debounce(Duration::from_millis(args.debounce_ms));
     
     // Set initial query if provided
     if let Some(query) = args.query {
@@ -64,3 +353,8 @@ async fn main() -> Result<()> {
     
     Ok(())
 }
+
+/// Id of the kitty window we run in, as kitty tells its children.
+fn own_window_id() -> Option<u32> {
+    std::env::var("KITTY_WINDOW_ID").ok().and_then(|id| id.parse().ok())
+}
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::kitty::marker::PinnedTerm;
+
+/// Pinned terms per kitty window, kept in
+/// `~/.local/share/kitty-fast-search/pins.json` so a later search in the same
+/// window keeps highlighting them alongside its own matches.
+///
+/// Window ids restart at 1 in every kitty, so windows are saved under the
+/// kitty instance they belong to. Pins of a window that cannot be named
+/// that way are only kept in memory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PinStore {
+    #[serde(default)]
+    windows: BTreeMap<String, Vec<PinnedTerm>>,
+    #[serde(skip)]
+    unsaved: Vec<PinnedTerm>,
+    #[serde(skip)]
+    instance: Option<String>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl PinStore {
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("kitty-fast-search").join("pins.json"))
+    }
+
+    /// Loads the saved pins; without a data directory, pins only last as
+    /// long as the store.
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Loads pins saved at `path`, which is created on the first `save`.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let mut store: Self = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(path)?)?
+        } else {
+            Self::default()
+        };
+        store.path = Some(path.to_path_buf());
+        Ok(store)
+    }
+
+    /// Which kitty the window ids passed to this store belong to, see
+    /// `KittyClient::instance`; without one, pins are not saved.
+    pub fn with_instance(mut self, instance: Option<String>) -> Self {
+        self.instance = instance;
+        self
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Pins of `window_id`; `None` is a window we know no id for.
+    pub fn pins(&self, window_id: Option<u32>) -> &[PinnedTerm] {
+        match self.window_key(window_id) {
+            Some(key) => self.windows.get(&key).map(Vec::as_slice).unwrap_or_default(),
+            None => &self.unsaved,
+        }
+    }
+
+    pub fn set_pins(&mut self, window_id: Option<u32>, pins: Vec<PinnedTerm>) {
+        match self.window_key(window_id) {
+            Some(key) if pins.is_empty() => {
+                self.windows.remove(&key);
+            }
+            Some(key) => {
+                self.windows.insert(key, pins);
+            }
+            None => self.unsaved = pins,
+        }
+    }
+
+    /// Forgets the pins of every window of our kitty, returning the ids of
+    /// the windows that had some.
+    pub fn clear_all(&mut self) -> Vec<Option<u32>> {
+        let mut cleared: Vec<Option<u32>> = Vec::new();
+        if let Some(instance) = &self.instance {
+            let prefix = format!("{}/", instance);
+            self.windows.retain(|key, _| match key.strip_prefix(&prefix) {
+                Some(id) => {
+                    cleared.push(id.parse().ok());
+                    false
+                }
+                None => true,
+            });
+        }
+        if !std::mem::take(&mut self.unsaved).is_empty() {
+            cleared.push(None);
+        }
+        cleared
+    }
+
+    fn window_key(&self, window_id: Option<u32>) -> Option<String> {
+        Some(format!("{}/{}", self.instance.as_ref()?, window_id?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pin(group: u8, query: &str) -> PinnedTerm {
+        PinnedTerm {
+            group,
+            query: query.to_string(),
+            case_sensitive: false,
+            regex: false,
+        }
+    }
+
+    #[test]
+    fn test_pins_round_trip_per_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("pins.json");
+
+        let mut store = PinStore::load_from(&path).unwrap().with_instance(Some("pid:10".to_string()));
+        store.set_pins(Some(3), vec![pin(1, "ERROR"), pin(2, "req-42")]);
+        store.set_pins(None, vec![pin(1, "host")]);
+        store.save().unwrap();
+
+        let store = PinStore::load_from(&path).unwrap().with_instance(Some("pid:10".to_string()));
+        assert_eq!(store.pins(Some(3)), [pin(1, "ERROR"), pin(2, "req-42")]);
+        assert!(store.pins(None).is_empty());
+        assert!(store.pins(Some(4)).is_empty());
+    }
+
+    #[test]
+    fn test_pins_belong_to_their_kitty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pins.json");
+
+        let mut store = PinStore::load_from(&path).unwrap().with_instance(Some("pid:10".to_string()));
+        store.set_pins(Some(3), vec![pin(1, "ERROR")]);
+        store.save().unwrap();
+
+        // Window 3 of a restarted or second kitty is another window
+        let store = PinStore::load_from(&path).unwrap().with_instance(Some("pid:11".to_string()));
+        assert!(store.pins(Some(3)).is_empty());
+        let store = PinStore::load_from(&path).unwrap().with_instance(None);
+        assert!(store.pins(Some(3)).is_empty());
+        assert_eq!(store.windows.keys().collect::<Vec<_>>(), ["pid:10/3"]);
+    }
+
+    #[test]
+    fn test_clear_all_reports_windows() {
+        let mut other = PinStore::default().with_instance(Some("pid:11".to_string()));
+        other.set_pins(Some(7), vec![pin(1, "ERROR")]);
+
+        let mut store = PinStore {
+            windows: other.windows,
+            ..PinStore::default()
+        }
+        .with_instance(Some("pid:10".to_string()));
+        store.set_pins(Some(3), vec![pin(1, "ERROR")]);
+        store.set_pins(None, vec![pin(1, "host")]);
+        store.set_pins(Some(5), Vec::new());
+
+        let mut cleared = store.clear_all();
+        cleared.sort();
+        assert_eq!(cleared, vec![None, Some(3)]);
+        assert!(store.pins(Some(3)).is_empty());
+        assert!(store.windows.contains_key("pid:11/7"));
+    }
+}
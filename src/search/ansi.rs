@@ -0,0 +1,455 @@
+use anyhow::{Result, anyhow};
+
+/// A terminal colour as set by SGR sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// One of the 256 palette entries; 0-7 are the basic and 8-15 the bright colours
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+/// A run of identically styled text, as byte offsets into the plain line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyleSpan {
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
+}
+
+/// Text with escape sequences stripped, plus the styles that applied to it.
+///
+/// `lines[i]` holds the spans of line `i` (0-based) and covers the line end
+/// to end, default-styled runs included.
+#[derive(Debug, Default)]
+pub struct StyledText {
+    pub text: String,
+    pub lines: Vec<Vec<StyleSpan>>,
+    /// Shell integration (OSC 133) marks, in the order they appeared
+    pub marks: Vec<PromptMark>,
+}
+
+/// Where shell integration marked a prompt, command or output boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptMark {
+    /// 1-based, as in `SearchResult`
+    pub line_number: u64,
+    /// Byte offset into the plain line
+    pub column: usize,
+    pub kind: MarkKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkKind {
+    /// `A`: a prompt starts
+    PromptStart,
+    /// `A;k=s`: a continuation prompt of a multi-line command starts
+    SecondaryPrompt,
+    /// `B`: the prompt ends and the command the user types starts
+    CommandStart,
+    /// `C`: the command runs and its output starts
+    OutputStart,
+    /// `D`: the command finished, with its exit status when reported
+    CommandEnd(Option<i32>),
+}
+
+impl MarkKind {
+    /// Parses the body of an OSC 133 sequence, e.g. `D;1` or `A;k=s`.
+    fn parse(body: &str) -> Option<Self> {
+        let mut params = body.strip_prefix("133;")?.split(';');
+        Some(match params.next()? {
+            "A" if params.any(|param| param == "k=s") => MarkKind::SecondaryPrompt,
+            "A" => MarkKind::PromptStart,
+            "B" => MarkKind::CommandStart,
+            "C" => MarkKind::OutputStart,
+            "D" => MarkKind::CommandEnd(params.next().and_then(|status| status.parse().ok())),
+            _ => return None,
+        })
+    }
+}
+
+impl StyledText {
+    /// Spans of `line_number` (1-based, as in `SearchResult`) clipped to `start..end`.
+    pub fn spans_in(&self, line_number: u64, start: usize, end: usize) -> Vec<StyleSpan> {
+        let Some(spans) = (line_number as usize)
+            .checked_sub(1)
+            .and_then(|idx| self.lines.get(idx))
+        else {
+            return Vec::new();
+        };
+
+        spans
+            .iter()
+            .filter(|span| span.start < end && span.end > start)
+            .map(|span| StyleSpan {
+                start: span.start.max(start),
+                end: span.end.min(end),
+                style: span.style,
+            })
+            .collect()
+    }
+}
+
+/// Parses `get-text --ansi` output into plain text and per-line style spans.
+///
+/// SGR sequences update the current style and OSC 133 shell integration
+/// marks are recorded; other CSI, OSC and two-byte escapes are dropped.
+pub fn parse(input: &str) -> StyledText {
+    let mut parser = Parser::default();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                Some((_, '[')) => {
+                    let mut params = String::new();
+                    for (_, c) in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            if c == 'm' {
+                                parser.apply_sgr(&params);
+                            }
+                            break;
+                        }
+                        params.push(c);
+                    }
+                }
+                Some((_, ']')) => {
+                    // OSC, terminated by BEL or ST (ESC \)
+                    let mut body = String::new();
+                    while let Some((_, c)) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' {
+                            chars.next_if(|&(_, c)| c == '\\');
+                            break;
+                        }
+                        body.push(c);
+                    }
+                    if let Some(kind) = MarkKind::parse(&body) {
+                        parser.mark(kind);
+                    }
+                }
+                _ => {}
+            },
+            '\n' => parser.newline(),
+            c => parser.push(c),
+        }
+    }
+
+    parser.finish()
+}
+
+#[derive(Default)]
+struct Parser {
+    text: String,
+    lines: Vec<Vec<StyleSpan>>,
+    marks: Vec<PromptMark>,
+    current_line: Vec<StyleSpan>,
+    line_start: usize,
+    style: Style,
+}
+
+impl Parser {
+    fn push(&mut self, c: char) {
+        let offset = self.text.len() - self.line_start;
+        self.text.push(c);
+        let end = offset + c.len_utf8();
+
+        match self.current_line.last_mut() {
+            Some(span) if span.style == self.style && span.end == offset => span.end = end,
+            _ => self.current_line.push(StyleSpan {
+                start: offset,
+                end,
+                style: self.style,
+            }),
+        }
+    }
+
+    fn mark(&mut self, kind: MarkKind) {
+        self.marks.push(PromptMark {
+            line_number: self.lines.len() as u64 + 1,
+            column: self.text.len() - self.line_start,
+            kind,
+        });
+    }
+
+    fn newline(&mut self) {
+        self.text.push('\n');
+        self.line_start = self.text.len();
+        self.lines.push(std::mem::take(&mut self.current_line));
+    }
+
+    fn finish(mut self) -> StyledText {
+        if !self.current_line.is_empty() || !self.text.ends_with('\n') {
+            self.lines.push(self.current_line);
+        }
+        StyledText {
+            text: self.text,
+            lines: self.lines,
+            marks: self.marks,
+        }
+    }
+
+    fn apply_sgr(&mut self, params: &str) {
+        if params.is_empty() {
+            self.style = Style::default();
+            return;
+        }
+
+        let groups: Vec<&str> = params.split(';').collect();
+        let mut i = 0;
+        while i < groups.len() {
+            let group = groups[i];
+            let mut sub = group.split(':');
+            let code: u16 = sub.next().and_then(|c| c.parse().ok()).unwrap_or(0);
+
+            match code {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                2 => self.style.dim = true,
+                3 => self.style.italic = true,
+                4 => self.style.underline = !matches!(sub.next(), Some("0")),
+                7 => self.style.reverse = true,
+                22 => {
+                    self.style.bold = false;
+                    self.style.dim = false;
+                }
+                23 => self.style.italic = false,
+                24 => self.style.underline = false,
+                27 => self.style.reverse = false,
+                30..=37 => self.style.fg = Some(Color::Indexed((code - 30) as u8)),
+                39 => self.style.fg = None,
+                40..=47 => self.style.bg = Some(Color::Indexed((code - 40) as u8)),
+                49 => self.style.bg = None,
+                90..=97 => self.style.fg = Some(Color::Indexed((code - 90 + 8) as u8)),
+                100..=107 => self.style.bg = Some(Color::Indexed((code - 100 + 8) as u8)),
+                38 | 48 | 58 => {
+                    // Extended colours come either as colon sub-parameters
+                    // (38:2::r:g:b) or as the following `;` parameters (38;2;r;g;b)
+                    let color = if group.contains(':') {
+                        extended_color(&sub.collect::<Vec<_>>())
+                    } else {
+                        let rest = &groups[i + 1..];
+                        let (color, used) = extended_color_params(rest);
+                        i += used;
+                        color
+                    };
+                    match code {
+                        38 => self.style.fg = color,
+                        48 => self.style.bg = color,
+                        _ => {} // underline colour is not tracked
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+fn extended_color(sub: &[&str]) -> Option<Color> {
+    let num = |s: Option<&&str>| s.and_then(|s| s.parse::<u8>().ok());
+    match sub.first().copied() {
+        Some("5") => num(sub.get(1)).map(Color::Indexed),
+        Some("2") => {
+            // An optional colour-space id may precede the components
+            let rgb = if sub.len() >= 5 { &sub[2..5] } else { sub.get(1..4)? };
+            Some(Color::Rgb(num(rgb.first())?, num(rgb.get(1))?, num(rgb.get(2))?))
+        }
+        _ => None,
+    }
+}
+
+fn extended_color_params(rest: &[&str]) -> (Option<Color>, usize) {
+    match rest.first().copied() {
+        Some("5") => (extended_color(&rest[..rest.len().min(2)]), 2),
+        Some("2") => (extended_color(&rest[..rest.len().min(4)]), 4),
+        _ => (None, 0),
+    }
+}
+
+/// Restricts matches to text drawn in a given style, e.g. "only red text".
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StyleFilter {
+    pub fg: Option<ColorSpec>,
+    pub bg: Option<ColorSpec>,
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+}
+
+/// A colour as written in a filter: a name matches both the basic and bright
+/// palette entry, an index or `#rrggbb` matches exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpec {
+    Named(u8),
+    Exact(Color),
+}
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+impl ColorSpec {
+    pub fn parse(spec: &str) -> Result<Self> {
+        if let Some(idx) = COLOR_NAMES.iter().position(|&name| name == spec) {
+            return Ok(ColorSpec::Named(idx as u8));
+        }
+        if let Some(hex) = spec.strip_prefix('#') {
+            let value = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)
+                .ok_or_else(|| anyhow!("Invalid colour {}", spec))?;
+            let [_, r, g, b] = value.to_be_bytes();
+            return Ok(ColorSpec::Exact(Color::Rgb(r, g, b)));
+        }
+        spec.parse::<u8>()
+            .map(|idx| ColorSpec::Exact(Color::Indexed(idx)))
+            .map_err(|_| anyhow!("Unknown colour {}, use a name, 0-255 or #rrggbb", spec))
+    }
+
+    pub fn matches(&self, color: Option<Color>) -> bool {
+        match (self, color) {
+            (ColorSpec::Named(idx), Some(Color::Indexed(c))) => c == *idx || c == idx + 8,
+            (ColorSpec::Exact(expected), Some(actual)) => *expected == actual,
+            _ => false,
+        }
+    }
+}
+
+impl StyleFilter {
+    /// Parses a comma-separated filter such as `red`, `fg=red,bold` or
+    /// `bg=#ff0000,no-italic`. A bare colour is a foreground colour; only
+    /// attributes can be negated.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut filter = StyleFilter::default();
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (negated, attr) = match part.strip_prefix("no-") {
+                Some(attr) => (true, attr),
+                None => (false, part),
+            };
+            if negated && !matches!(attr, "bold" | "italic" | "underline") {
+                return Err(anyhow!(
+                    "Cannot negate {}: no- only applies to bold, italic and underline",
+                    attr
+                ));
+            }
+            match attr.split_once('=') {
+                Some(("fg", color)) => filter.fg = Some(ColorSpec::parse(color)?),
+                Some(("bg", color)) => filter.bg = Some(ColorSpec::parse(color)?),
+                Some((key, _)) => return Err(anyhow!("Unknown style filter key {}", key)),
+                None => match attr {
+                    "bold" => filter.bold = Some(!negated),
+                    "italic" => filter.italic = Some(!negated),
+                    "underline" => filter.underline = Some(!negated),
+                    color => filter.fg = Some(ColorSpec::parse(color)?),
+                },
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, style: &Style) -> bool {
+        self.fg.is_none_or(|fg| fg.matches(style.fg))
+            && self.bg.is_none_or(|bg| bg.matches(style.bg))
+            && self.bold.is_none_or(|bold| bold == style.bold)
+            && self.italic.is_none_or(|italic| italic == style.italic)
+            && self.underline.is_none_or(|underline| underline == style.underline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strips_sequences_and_tracks_columns() {
+        let styled = parse("ok \x1b[31mfailed\x1b[0m done\nnext");
+        assert_eq!(styled.text, "ok failed done\nnext");
+        assert_eq!(styled.lines.len(), 2);
+
+        let red = Style {
+            fg: Some(Color::Indexed(1)),
+            ..Style::default()
+        };
+        assert_eq!(
+            styled.lines[0],
+            vec![
+                StyleSpan { start: 0, end: 3, style: Style::default() },
+                StyleSpan { start: 3, end: 9, style: red },
+                StyleSpan { start: 9, end: 14, style: Style::default() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extended_colours() {
+        let styled = parse("\x1b[38;5;196ma\x1b[48:2::1:2:3mb\x1b[38;2;10;20;30mc");
+        let spans = &styled.lines[0];
+        assert_eq!(spans[0].style.fg, Some(Color::Indexed(196)));
+        assert_eq!(spans[1].style.bg, Some(Color::Rgb(1, 2, 3)));
+        assert_eq!(spans[2].style.fg, Some(Color::Rgb(10, 20, 30)));
+    }
+
+    #[test]
+    fn test_osc_sequences_are_dropped() {
+        let styled = parse("\x1b]133;A\x1b\\$ ls\x1b]2;title\x07\n");
+        assert_eq!(styled.text, "$ ls\n");
+        assert_eq!(styled.lines.len(), 1);
+    }
+
+    #[test]
+    fn test_prompt_marks_are_recorded() {
+        let styled = parse("\x1b]133;A\x1b\\$ \x1b]133;B\x07make\n\x1b]133;C\x1b\\ok\n\x1b]133;D;2\x07");
+        let marks: Vec<_> = styled.marks.iter().map(|m| (m.line_number, m.column, m.kind)).collect();
+        assert_eq!(
+            marks,
+            vec![
+                (1, 0, MarkKind::PromptStart),
+                (1, 2, MarkKind::CommandStart),
+                (2, 0, MarkKind::OutputStart),
+                (3, 0, MarkKind::CommandEnd(Some(2))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multibyte_offsets() {
+        let styled = parse("é\x1b[1mü");
+        assert_eq!(styled.lines[0][1].start, 2);
+        assert_eq!(styled.lines[0][1].end, 4);
+        assert!(styled.lines[0][1].style.bold);
+    }
+
+    #[test]
+    fn test_style_filter() {
+        let filter = StyleFilter::parse("red,bold").unwrap();
+        let bright_red_bold = Style {
+            fg: Some(Color::Indexed(9)),
+            bold: true,
+            ..Style::default()
+        };
+        assert!(filter.matches(&bright_red_bold));
+        assert!(!filter.matches(&Style::default()));
+
+        let hex = StyleFilter::parse("bg=#ff0000").unwrap();
+        assert_eq!(hex.bg, Some(ColorSpec::Exact(Color::Rgb(255, 0, 0))));
+        assert!(StyleFilter::parse("fg=chartreuse").is_err());
+
+        // Colours cannot be negated, rather than silently meaning the colour
+        assert!(StyleFilter::parse("no-red").is_err());
+        assert!(StyleFilter::parse("no-fg=red").is_err());
+        assert_eq!(StyleFilter::parse("no-bold").unwrap().bold, Some(false));
+    }
+}
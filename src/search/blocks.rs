@@ -0,0 +1,236 @@
+use std::ops::Range;
+
+use crate::search::ansi::{MarkKind, StyledText};
+use crate::search::engine::SearchResult;
+
+/// One command run at a shell prompt: the prompt and command line(s), the
+/// output, and how it exited.
+///
+/// Line numbers are 1-based like `SearchResult::line_number`; ranges are
+/// half-open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandBlock {
+    /// Position in the buffer, starting at 0 for the oldest command
+    pub index: usize,
+    /// The prompt and the command typed at it, continuation lines included
+    pub command_lines: Range<u64>,
+    /// The command text, without the prompt when the shell marks its end
+    pub command: String,
+    /// Empty while the command has not produced output
+    pub output_lines: Range<u64>,
+    /// Only known when the shell reported it with an `OSC 133;D` mark
+    pub exit_status: Option<i32>,
+}
+
+impl CommandBlock {
+    pub fn contains(&self, line_number: u64) -> bool {
+        (self.command_lines.start..self.output_lines.end.max(self.command_lines.end))
+            .contains(&line_number)
+    }
+
+    pub fn failed(&self) -> bool {
+        self.exit_status.is_some_and(|status| status != 0)
+    }
+}
+
+/// Which part of the command blocks to search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BlockFilter {
+    /// Only the command lines typed at prompts
+    Commands,
+    /// Only command output
+    Outputs,
+    /// Only output of commands that exited with a non-zero status
+    Failed,
+}
+
+impl BlockFilter {
+    pub fn keeps(&self, block: &CommandBlock, line_number: u64) -> bool {
+        match self {
+            BlockFilter::Commands => block.command_lines.contains(&line_number),
+            BlockFilter::Outputs => block.output_lines.contains(&line_number),
+            BlockFilter::Failed => block.failed() && block.output_lines.contains(&line_number),
+        }
+    }
+}
+
+/// Splits a capture into command blocks using its shell integration marks.
+/// Text before the first prompt belongs to no block.
+pub fn segment(styled: &StyledText) -> Vec<CommandBlock> {
+    let lines: Vec<&str> = styled.text.lines().collect();
+    let end = lines.len() as u64 + 1;
+    let mut blocks: Vec<CommandBlock> = Vec::new();
+    // Where the command text starts on the prompt line, once marked
+    let mut command_column = 0;
+
+    for mark in &styled.marks {
+        match mark.kind {
+            MarkKind::PromptStart => {
+                if let Some(block) = blocks.last_mut() {
+                    if !block.output_lines.is_empty() {
+                        block.output_lines.end = mark.line_number;
+                    }
+                }
+                blocks.push(CommandBlock {
+                    index: blocks.len(),
+                    command_lines: mark.line_number..mark.line_number + 1,
+                    command: String::new(),
+                    output_lines: mark.line_number + 1..mark.line_number + 1,
+                    exit_status: None,
+                });
+                command_column = 0;
+            }
+            MarkKind::SecondaryPrompt => {
+                if let Some(block) = blocks.last_mut() {
+                    if block.output_lines.is_empty() {
+                        block.command_lines.end = mark.line_number + 1;
+                        block.output_lines = mark.line_number + 1..mark.line_number + 1;
+                    }
+                }
+            }
+            MarkKind::CommandStart => {
+                if let Some(block) = blocks.last_mut() {
+                    if block.command_lines.start == mark.line_number {
+                        command_column = mark.column;
+                    }
+                }
+            }
+            MarkKind::OutputStart => {
+                if let Some(block) = blocks.last_mut() {
+                    block.command_lines.end = mark.line_number.max(block.command_lines.start + 1);
+                    block.output_lines = mark.line_number..end;
+                    block.command = command_text(&lines, &block.command_lines, command_column);
+                }
+            }
+            MarkKind::CommandEnd(status) => {
+                if let Some(block) = blocks.last_mut() {
+                    block.exit_status = status;
+                }
+            }
+        }
+    }
+
+    // The last prompt usually has no output yet; its command is whatever
+    // was typed so far
+    if let Some(block) = blocks.last_mut() {
+        if block.command.is_empty() {
+            block.command = command_text(&lines, &block.command_lines, command_column);
+        }
+    }
+    blocks
+}
+
+fn command_text(lines: &[&str], command_lines: &Range<u64>, column: usize) -> String {
+    command_lines
+        .clone()
+        .filter_map(|line_number| lines.get(line_number as usize - 1))
+        .enumerate()
+        .map(|(i, line)| if i == 0 { line.get(column..).unwrap_or(line) } else { line })
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The block `line_number` is in, if any.
+pub fn block_at(blocks: &[CommandBlock], line_number: u64) -> Option<&CommandBlock> {
+    let idx = blocks.partition_point(|block| block.command_lines.start <= line_number);
+    idx.checked_sub(1)
+        .map(|idx| &blocks[idx])
+        .filter(|block| block.contains(line_number))
+}
+
+/// Records the exit status kitty reports for the last command that ran,
+/// for captures without `OSC 133;D` marks.
+pub fn set_last_exit_status(blocks: &mut [CommandBlock], status: i32) {
+    if let Some(block) = blocks.iter_mut().rev().find(|block| !block.output_lines.is_empty()) {
+        block.exit_status.get_or_insert(status);
+    }
+}
+
+/// Tags `results` with the index of their command block and, with a
+/// `filter`, drops matches outside the part of the blocks it selects.
+pub fn assign(results: &mut Vec<SearchResult>, blocks: &[CommandBlock], filter: Option<BlockFilter>) {
+    results.retain_mut(|result| {
+        let block = block_at(blocks, result.line_number);
+        result.command_index = block.map(|block| block.index);
+        match (filter, block) {
+            (None, _) => true,
+            (Some(filter), Some(block)) => filter.keeps(block, result.line_number),
+            (Some(_), None) => false,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::ansi;
+    use crate::search::SearchEngine;
+
+    // Two finished commands, the second failing, and a fresh prompt. Marks
+    // as zsh with kitty's shell integration emits them.
+    const CAPTURE: &str = "Last login: today\n\
+        \x1b]133;A\x1b\\~/src $ \x1b]133;B\x1b\\make\n\
+        \x1b]133;C\x1b\\cc -o app main.c\n\
+        done\n\
+        \x1b]133;D;0\x1b\\\x1b]133;A\x1b\\~/src $ \x1b]133;B\x1b\\make test\n\
+        \x1b]133;C\x1b\\test app ... FAILED\n\
+        error: 1 test failed\n\
+        \x1b]133;D;2\x1b\\\x1b]133;A\x1b\\~/src $ \x1b]133;B\x1b\\\n";
+
+    #[test]
+    fn test_segments_commands() {
+        let blocks = segment(&ansi::parse(CAPTURE));
+        assert_eq!(blocks.len(), 3);
+
+        assert_eq!(blocks[0].command, "make");
+        assert_eq!(blocks[0].command_lines, 2..3);
+        assert_eq!(blocks[0].output_lines, 3..5);
+        assert_eq!(blocks[0].exit_status, Some(0));
+
+        assert_eq!(blocks[1].command, "make test");
+        assert!(blocks[1].failed());
+        assert_eq!(blocks[2].command, "");
+        assert!(blocks[2].output_lines.is_empty());
+
+        assert_eq!(block_at(&blocks, 1), None);
+        assert_eq!(block_at(&blocks, 4).map(|b| b.index), Some(0));
+        assert_eq!(block_at(&blocks, 7).map(|b| b.index), Some(1));
+    }
+
+    #[test]
+    fn test_filters_matches_by_block_part() {
+        let styled = ansi::parse(CAPTURE);
+        let blocks = segment(&styled);
+        let engine = SearchEngine::new(1000, true, false).unwrap();
+        let search = |filter| {
+            let mut results = engine.search_text(&styled.text, "make").unwrap();
+            results.extend(engine.search_text(&styled.text, "test").unwrap());
+            assign(&mut results, &blocks, filter);
+            results
+                .iter()
+                .map(|r| (r.line_number, r.command_index))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(search(None), vec![(2, Some(0)), (5, Some(1)), (5, Some(1)), (6, Some(1)), (7, Some(1))]);
+        assert_eq!(search(Some(BlockFilter::Commands)), vec![(2, Some(0)), (5, Some(1)), (5, Some(1))]);
+        assert_eq!(search(Some(BlockFilter::Failed)), vec![(6, Some(1)), (7, Some(1))]);
+    }
+
+    #[test]
+    fn test_kitty_captures_without_exit_marks() {
+        // kitty keeps prompt and output marks in the scrollback, but not
+        // exit statuses
+        let styled = ansi::parse(
+            "\x1b]133;A\x1b\\$ false\n\x1b]133;C\x1b\\\n\x1b]133;A\x1b\\$ \n",
+        );
+        let mut blocks = segment(&styled);
+        assert_eq!(blocks[0].command, "$ false");
+        assert_eq!(blocks[0].exit_status, None);
+
+        set_last_exit_status(&mut blocks, 1);
+        assert!(blocks[0].failed());
+        assert_eq!(blocks[1].exit_status, None);
+    }
+}
@@ -1,7 +1,4 @@
 use anyhow::Result;
-use memmap2::Mmap;
-use std::fs::File;
-use std::path::Path;
 
 #[allow(dead_code)]
 pub struct BufferManager {
@@ -14,19 +11,6 @@ impl BufferManager {
         Self { max_size }
     }
 
-    pub fn load_from_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
-        let file = File::open(path)?;
-        let mmap = unsafe { Mmap::map(&file)? };
-        
-        if mmap.len() > self.max_size {
-            // Take only the last max_size bytes for recent data
-            let start = mmap.len() - self.max_size;
-            Ok(mmap[start..].to_vec())
-        } else {
-            Ok(mmap.to_vec())
-        }
-    }
-
     pub fn load_from_string(&self, content: String) -> Result<Vec<u8>> {
         let bytes = content.into_bytes();
         if bytes.len() > self.max_size {
@@ -38,6 +22,42 @@ impl BufferManager {
     }
 
     pub fn chunk_buffer<'a>(&self, buffer: &'a [u8], chunk_size: usize) -> Vec<&'a [u8]> {
-        buffer.chunks(chunk_size).collect()
+        chunk_lines(buffer, chunk_size)
     }
-}
\ No newline at end of file
+}
+
+/// Splits `buffer` into chunks of about `chunk_size` bytes, each ending
+/// after a newline so no line is cut in half. A chunk only runs over
+/// `chunk_size` to finish its last line.
+pub fn chunk_lines(buffer: &[u8], chunk_size: usize) -> Vec<&[u8]> {
+    let chunk_size = chunk_size.max(1);
+    let mut chunks = Vec::with_capacity(buffer.len() / chunk_size + 1);
+    let mut rest = buffer;
+    while !rest.is_empty() {
+        let end = if rest.len() > chunk_size {
+            rest[chunk_size - 1..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(rest.len(), |i| chunk_size + i)
+        } else {
+            rest.len()
+        };
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_end_at_line_boundaries() {
+        let chunks = chunk_lines(b"one\ntwo\nthree\nfour", 5);
+        assert_eq!(chunks, vec![&b"one\ntwo\n"[..], b"three\n", b"four"]);
+        assert_eq!(chunk_lines(b"ab\ncd\n", 3), vec![&b"ab\n"[..], b"cd\n"]);
+        assert!(chunk_lines(b"", 3).is_empty());
+    }
+}
@@ -1,28 +1,140 @@
 use anyhow::Result;
 use grep::regex::RegexMatcher;
-use grep::searcher::{BinaryDetection, SearcherBuilder};
-use grep::searcher::sinks::UTF8;
+use grep::searcher::{BinaryDetection, Searcher, SearcherBuilder, Sink, SinkMatch};
 use grep::matcher::Matcher;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::ops::Range;
 use std::sync::Arc;
 use lru::LruCache;
+use rayon::prelude::*;
 use std::num::NonZeroUsize;
 
+use crate::search::ansi::{StyleFilter, StyleSpan, StyledText};
+use crate::search::buffer::chunk_lines;
+
+/// Buffers at least this big are searched in parallel chunks.
+pub const PARALLEL_THRESHOLD: usize = 4 * 1024 * 1024;
+/// Size of each chunk of a parallel search; small enough to keep every core
+/// busy until the end, big enough that merging stays cheap.
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+/// How much of a buffer grep-searcher checks for binary data up front
+/// (its default buffer capacity); later NUL bytes only count in matches.
+const BINARY_DETECTION_HEAD: usize = 64 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct SearchResult {
     pub line_number: u64,
     pub line: String,
-    #[allow(dead_code)]
     pub match_start: usize,
-    #[allow(dead_code)]
     pub match_end: usize,
+    /// Styles of the matched text, when searching ANSI-captured buffers
+    pub styles: Vec<StyleSpan>,
+    /// Window and tab the match was found in, when searching several windows
+    pub window_id: Option<u32>,
+    pub tab_id: Option<u32>,
+    /// Index of the command block the match is in, when searching by command
+    pub command_index: Option<usize>,
 }
 
+/// A match found by `SearchEngine::search_bytes`, as byte offsets into the
+/// searched buffer. Nothing is decoded until `line` or `to_result` is called.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteMatch {
+    pub line_number: u64,
+    /// The matching line, terminator included
+    pub line: Range<usize>,
+    /// The match itself
+    pub bytes: Range<usize>,
+}
+
+impl ByteMatch {
+    /// The matching line, decoded for display; invalid UTF-8 is replaced.
+    pub fn line<'a>(&self, buffer: &'a [u8]) -> Cow<'a, str> {
+        String::from_utf8_lossy(&buffer[self.line.clone()])
+    }
+
+    /// The match as a `SearchResult`, with its range into the decoded line.
+    pub fn to_result(&self, buffer: &[u8]) -> SearchResult {
+        let line = self.line(buffer);
+        let raw = &buffer[self.line.clone()];
+        let (start, end) = (self.bytes.start - self.line.start, self.bytes.end - self.line.start);
+        // Each replaced byte sequence takes the three bytes of U+FFFD
+        let (match_start, match_end) = match line {
+            Cow::Borrowed(_) => (start, end),
+            Cow::Owned(_) => {
+                let decoded_len = |bytes: &[u8]| String::from_utf8_lossy(bytes).len();
+                let match_start = decoded_len(&raw[..start]);
+                (match_start, match_start + decoded_len(&raw[start..end]))
+            }
+        };
+        SearchResult {
+            line_number: self.line_number,
+            line: line.into_owned(),
+            match_start,
+            match_end,
+            styles: Vec::new(),
+            window_id: None,
+            tab_id: None,
+            command_index: None,
+        }
+    }
+}
+
+/// Text captured from one kitty window, for `SearchEngine::search_windows`.
+#[derive(Debug, Clone)]
+pub struct WindowText {
+    pub window_id: u32,
+    pub tab_id: u32,
+    pub text: String,
+}
+
+/// Cheap to clone; clones share the result cache.
+#[derive(Clone)]
 pub struct SearchEngine {
     #[allow(dead_code)]
     max_buffer_size: usize,
     case_sensitive: bool,
     regex_enabled: bool,
-    cache: Arc<std::sync::Mutex<LruCache<String, Vec<SearchResult>>>>,
+    parallel_threshold: usize,
+    chunk_size: usize,
+    cache: Arc<std::sync::Mutex<LruCache<String, CachedSearch>>>,
+    /// Pattern of the last `search_text`, which a longer query may narrow
+    last_pattern: Arc<std::sync::Mutex<Option<String>>>,
+}
+
+/// Results of one pattern over the last text it was searched in.
+struct CachedSearch {
+    /// Length and hash of that text
+    len: usize,
+    hash: u64,
+    /// Where its last line starts and how many lines come before it. Text
+    /// appended later may continue that line, so it is searched again.
+    last_line_start: usize,
+    lines_before: u64,
+    results: Vec<SearchResult>,
+}
+
+impl CachedSearch {
+    fn new(text: &[u8], hash: u64, results: Vec<SearchResult>) -> Self {
+        let last_line_start = last_line_start(text);
+        Self {
+            len: text.len(),
+            hash,
+            last_line_start,
+            lines_before: count_lines(&text[..last_line_start]),
+            results,
+        }
+    }
+}
+
+fn last_line_start(text: &[u8]) -> usize {
+    text.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1)
+}
+
+fn count_lines(text: &[u8]) -> u64 {
+    text.iter().filter(|&&b| b == b'\n').count() as u64
 }
 
 impl SearchEngine {
@@ -32,26 +144,169 @@ impl SearchEngine {
             max_buffer_size,
             case_sensitive,
             regex_enabled,
+            parallel_threshold: PARALLEL_THRESHOLD,
+            chunk_size: CHUNK_SIZE,
             cache: Arc::new(std::sync::Mutex::new(LruCache::new(cache_size))),
+            last_pattern: Arc::new(std::sync::Mutex::new(None)),
         })
     }
 
+    /// Searches buffers of at least `threshold` bytes in parallel, split
+    /// into chunks of about `chunk_size` bytes.
+    #[allow(dead_code)]
+    pub fn with_parallel_search(mut self, threshold: usize, chunk_size: usize) -> Self {
+        self.parallel_threshold = threshold;
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn case_sensitive(&self) -> bool {
+        self.case_sensitive
+    }
+
+    pub fn regex_enabled(&self) -> bool {
+        self.regex_enabled
+    }
+
     pub fn search_text(&self, text: &str, pattern: &str) -> Result<Vec<SearchResult>> {
         if pattern.is_empty() {
             return Ok(Vec::new());
         }
 
-        // Check cache first
-        let cache_key = format!("{}:{}:{}:{}", pattern, self.case_sensitive, self.regex_enabled, text.len());
+        // One entry per pattern, for the last text it was searched in. The
+        // hash tells whether that text is still there, whole or with more
+        // appended; hashing is much cheaper than searching.
+        let cache_key = format!("{}:{}:{}", pattern, self.case_sensitive, self.regex_enabled);
+        let cached = self.cache.lock().ok().and_then(|mut cache| cache.pop(&cache_key));
+        let bytes = text.as_bytes();
+
+        let mut hasher = DefaultHasher::new();
+        let mut hashed = 0;
+        let mut reusable = None;
+        if let Some(entry) = cached.filter(|entry| entry.len <= bytes.len()) {
+            hasher.write(&bytes[..entry.len]);
+            hashed = entry.len;
+            if hasher.clone().finish() == entry.hash {
+                reusable = Some(entry);
+            }
+        }
+        hasher.write(&bytes[hashed..]);
+        let hash = hasher.finish();
+
+        let entry = match reusable {
+            Some(entry) if entry.len == bytes.len() => entry,
+            Some(entry) => self.search_appended(entry, text, hash, pattern)?,
+            None => {
+                let matcher = self.matcher(pattern)?;
+                let results = match self.narrowed_lines(pattern, bytes.len(), hash) {
+                    Some((lines, line_numbers)) => {
+                        let mut results = self.search_with(&matcher, &lines)?;
+                        for result in &mut results {
+                            result.line_number = line_numbers[result.line_number as usize - 1];
+                        }
+                        results
+                    }
+                    None => self.search_with(&matcher, text)?,
+                };
+                CachedSearch::new(bytes, hash, results)
+            }
+        };
+
+        let results = entry.results.clone();
         if let Ok(mut cache) = self.cache.lock() {
-            if let Some(cached_results) = cache.get(&cache_key) {
-                return Ok(cached_results.clone());
+            cache.put(cache_key, entry);
+        }
+        if let Ok(mut last_pattern) = self.last_pattern.lock() {
+            *last_pattern = Some(pattern.to_string());
+        }
+        Ok(results)
+    }
+
+    /// When `pattern` refines the last literal query on the same text, e.g.
+    /// `erro` after `err`, only lines that matched it can match `pattern`.
+    /// Returns those lines joined, with their line numbers.
+    fn narrowed_lines(&self, pattern: &str, len: usize, hash: u64) -> Option<(String, Vec<u64>)> {
+        if self.regex_enabled {
+            return None;
+        }
+        let previous = self.last_pattern.lock().ok()?.clone()?;
+        if !self.refines(pattern, &previous) {
+            return None;
+        }
+
+        let key = format!("{}:{}:{}", previous, self.case_sensitive, self.regex_enabled);
+        let cache = self.cache.lock().ok()?;
+        let entry = cache.peek(&key).filter(|entry| entry.len == len && entry.hash == hash)?;
+
+        let mut lines = String::new();
+        let mut line_numbers = Vec::new();
+        for result in &entry.results {
+            // Results come one per match, so lines with several repeat
+            if line_numbers.last() != Some(&result.line_number) {
+                lines.push_str(&result.line);
+                line_numbers.push(result.line_number);
             }
         }
+        Some((lines, line_numbers))
+    }
 
-        let mut results = Vec::new();
-        
-        // Create regex pattern
+    /// Whether every line matching literal `pattern` also matches `previous`.
+    fn refines(&self, pattern: &str, previous: &str) -> bool {
+        if self.case_sensitive {
+            pattern.contains(previous)
+        } else {
+            // Unicode case folding does not map substrings to substrings
+            pattern.is_ascii()
+                && previous.is_ascii()
+                && pattern.to_ascii_lowercase().contains(&previous.to_ascii_lowercase())
+        }
+    }
+
+    /// Updates `entry` for `text`, which is its text with lines appended,
+    /// searching only from its last line on.
+    fn search_appended(&self, mut entry: CachedSearch, text: &str, hash: u64, pattern: &str) -> Result<CachedSearch> {
+        let lines_before = entry.lines_before;
+        let mut tail = self.search_with(&self.matcher(pattern)?, &text[entry.last_line_start..])?;
+        for result in &mut tail {
+            result.line_number += lines_before;
+        }
+        entry.results.retain(|result| result.line_number <= lines_before);
+        entry.results.append(&mut tail);
+
+        let bytes = text.as_bytes();
+        let last_line = entry.last_line_start + last_line_start(&bytes[entry.last_line_start..]);
+        entry.lines_before += count_lines(&bytes[entry.last_line_start..last_line]);
+        entry.last_line_start = last_line;
+        entry.len = bytes.len();
+        entry.hash = hash;
+        Ok(entry)
+    }
+
+    /// Searches the text of several windows in parallel, tagging each result
+    /// with the window and tab it was found in. Results follow the order of
+    /// `windows`, then line order within each window.
+    pub fn search_windows(&self, windows: &[WindowText], pattern: &str) -> Result<Vec<SearchResult>> {
+        if pattern.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let matcher = self.matcher(pattern)?;
+        let per_window = windows
+            .par_iter()
+            .map(|window| {
+                let mut results = self.search_with(&matcher, &window.text)?;
+                for result in &mut results {
+                    result.window_id = Some(window.window_id);
+                    result.tab_id = Some(window.tab_id);
+                }
+                Ok(results)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(per_window.into_iter().flatten().collect())
+    }
+
+    fn matcher(&self, pattern: &str) -> Result<RegexMatcher> {
         let regex_pattern = if self.regex_enabled {
             pattern.to_string()
         } else {
@@ -63,53 +318,100 @@ impl SearchEngine {
         } else {
             format!("(?i){}", regex_pattern)
         };
-        
-        // Create regex matcher
-        let matcher = RegexMatcher::new(&final_pattern)?;
-        
-        // Configure searcher
-        let mut searcher = SearcherBuilder::new()
-            .binary_detection(BinaryDetection::quit(b'\x00'))
-            .line_number(true)
-            .build();
-
-        // Search the text
-        searcher.search_slice(
-            &matcher,
-            text.as_bytes(),
-            UTF8(|lnum, line| {
-                let line_bytes = line.as_bytes();
-                let line_str = std::str::from_utf8(line_bytes).unwrap_or("");
-                // Find all matches in this line
-                let mut match_start = 0;
-                while let Some(mat) = matcher.find_at(line_bytes, match_start)? {
-                    results.push(SearchResult {
-                        line_number: lnum,
-                        line: line_str.to_string(),
-                        match_start: mat.start(),
-                        match_end: mat.end(),
-                    });
-                    match_start = mat.end();
-                    if match_start >= line_bytes.len() {
-                        break;
-                    }
-                }
-                Ok(true)
-            }),
-        )?;
 
-        // Cache the results
-        if let Ok(mut cache) = self.cache.lock() {
-            cache.put(cache_key, results.clone());
-        }
+        Ok(RegexMatcher::new(&final_pattern)?)
+    }
 
-        Ok(results)
+    fn search_with(&self, matcher: &RegexMatcher, text: &str) -> Result<Vec<SearchResult>> {
+        let buffer = text.as_bytes();
+        Ok(self
+            .matches_in(matcher, buffer)?
+            .iter()
+            .map(|mat| mat.to_result(buffer))
+            .collect())
     }
 
+    /// Searches `buffer` as is, reporting byte offsets. Unlike `search_text`
+    /// it needs neither valid UTF-8 nor a copy of the buffer, and decodes
+    /// nothing. The overlay searches captures the same way, through
+    /// `search_text`.
     #[allow(dead_code)]
-    pub fn search_buffer(&self, buffer: &[u8], pattern: &str) -> Result<Vec<SearchResult>> {
-        let text = String::from_utf8_lossy(buffer);
-        self.search_text(&text, pattern)
+    pub fn search_bytes(&self, buffer: &[u8], pattern: &str) -> Result<Vec<ByteMatch>> {
+        if pattern.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.matches_in(&self.matcher(pattern)?, buffer)
+    }
+
+    fn matches_in(&self, matcher: &RegexMatcher, buffer: &[u8]) -> Result<Vec<ByteMatch>> {
+        if buffer.len() < self.parallel_threshold {
+            return Ok(search_chunk(matcher, buffer, BinaryDetection::quit(b'\x00'))?.matches);
+        }
+
+        // A whole-buffer search only looks for NUL bytes at its start and in
+        // matching lines. Chunks must not look at the start of each chunk
+        // too, so the start is checked once here and lines by the sink.
+        let head = &buffer[..buffer.len().min(BINARY_DETECTION_HEAD)];
+        if head.contains(&b'\x00') {
+            return Ok(Vec::new());
+        }
+
+        // Chunks end at line boundaries, so each can be searched on its own;
+        // only line numbers and offsets need shifting once all are done
+        let mut start = 0;
+        let chunks: Vec<(usize, &[u8])> = chunk_lines(buffer, self.chunk_size)
+            .into_iter()
+            .map(|chunk| {
+                start += chunk.len();
+                (start - chunk.len(), chunk)
+            })
+            .collect();
+        let searched = chunks
+            .par_iter()
+            .map(|&(start, chunk)| {
+                let lines = chunk.iter().filter(|&&b| b == b'\n').count() as u64;
+                Ok((start, lines, search_chunk(matcher, chunk, BinaryDetection::none())?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut matches = Vec::with_capacity(searched.iter().map(|(_, _, sink)| sink.matches.len()).sum());
+        let mut lines_before = 0;
+        for (start, lines, sink) in searched {
+            matches.extend(sink.matches.into_iter().map(|mat| ByteMatch {
+                line_number: mat.line_number + lines_before,
+                line: mat.line.start + start..mat.line.end + start,
+                bytes: mat.bytes.start + start..mat.bytes.end + start,
+            }));
+            // A matching line with binary data ends the search
+            if sink.binary {
+                break;
+            }
+            lines_before += lines;
+        }
+        Ok(matches)
+    }
+
+    /// Searches the plain text of an ANSI capture, attaching the styles of
+    /// each match and, with a `filter`, keeping only matches drawn entirely
+    /// in that style.
+    pub fn search_styled(
+        &self,
+        styled: &StyledText,
+        pattern: &str,
+        filter: Option<&StyleFilter>,
+    ) -> Result<Vec<SearchResult>> {
+        let mut results = self.search_text(&styled.text, pattern)?;
+        for result in &mut results {
+            result.styles = styled.spans_in(result.line_number, result.match_start, result.match_end);
+        }
+
+        if let Some(filter) = filter {
+            results.retain(|result| {
+                !result.styles.is_empty()
+                    && result.styles.iter().all(|span| filter.matches(&span.style))
+            });
+        }
+        Ok(results)
     }
 
     #[allow(dead_code)]
@@ -129,6 +431,72 @@ impl SearchEngine {
     }
 }
 
+/// Searches one buffer or chunk on the current thread.
+fn search_chunk<'m>(
+    matcher: &'m RegexMatcher,
+    chunk: &[u8],
+    binary_detection: BinaryDetection,
+) -> Result<MatchSink<'m>> {
+    let mut searcher = SearcherBuilder::new()
+        .binary_detection(binary_detection)
+        .line_number(true)
+        .build();
+
+    let mut sink = MatchSink {
+        matcher,
+        matches: Vec::new(),
+        binary: false,
+    };
+    searcher.search_slice(matcher, chunk, &mut sink)?;
+    Ok(sink)
+}
+
+/// Collects every match on each matching line, with absolute offsets.
+struct MatchSink<'m> {
+    matcher: &'m RegexMatcher,
+    matches: Vec<ByteMatch>,
+    /// Whether the search stopped at binary data
+    binary: bool,
+}
+
+impl Sink for MatchSink<'_> {
+    type Error = std::io::Error;
+
+    fn binary_data(&mut self, _searcher: &Searcher, _offset: u64) -> std::io::Result<bool> {
+        self.binary = true;
+        Ok(false)
+    }
+
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch<'_>) -> std::io::Result<bool> {
+        let line = mat.bytes();
+        // The searcher does this itself unless binary detection is off
+        if line.contains(&b'\x00') {
+            self.binary = true;
+            return Ok(false);
+        }
+        let line_start = mat.absolute_byte_offset() as usize;
+        let line_number = mat.line_number().unwrap_or_default();
+
+        let mut at = 0;
+        while at <= line.len() {
+            let Some(found) = self.matcher.find_at(line, at).map_err(std::io::Error::other)? else {
+                break;
+            };
+            self.matches.push(ByteMatch {
+                line_number,
+                line: line_start..line_start + line.len(),
+                bytes: line_start + found.start()..line_start + found.end(),
+            });
+            // Step past empty matches so they cannot repeat forever
+            at = if found.is_empty() { found.end() + 1 } else { found.end() };
+            if at >= line.len() {
+                break;
+            }
+        }
+        Ok(true)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -202,11 +570,177 @@ mod tests {
         assert_eq!(engine.get_cache_size(), 2);
     }
 
+    #[test]
+    fn test_cache_sees_changed_text_of_same_length() {
+        let engine = SearchEngine::new(1000, true, false).unwrap();
+        assert_eq!(engine.search_text("ERROR one\n", "ERROR").unwrap().len(), 1);
+        assert_eq!(engine.search_text("WARN  one\n", "ERROR").unwrap().len(), 0);
+        assert_eq!(engine.get_cache_size(), 1);
+    }
+
+    #[test]
+    fn test_cache_searches_appended_lines() {
+        let engine = SearchEngine::new(1000, true, false).unwrap();
+        let fresh = || SearchEngine::new(1000, true, false).unwrap();
+        let summary = |results: &[SearchResult]| {
+            results
+                .iter()
+                .map(|r| (r.line_number, r.line.clone(), r.match_start))
+                .collect::<Vec<_>>()
+        };
+
+        let mut text = String::from("ERROR a\nok\nhalf an ERR");
+        assert_eq!(engine.search_text(&text, "ERROR").unwrap().len(), 1);
+
+        // The unfinished last line is completed, then more lines arrive
+        for more in ["OR line\n", "ERROR b\nok\n", "", "ERROR c ERROR"] {
+            text.push_str(more);
+            let results = engine.search_text(&text, "ERROR").unwrap();
+            let expected = fresh().search_text(&text, "ERROR").unwrap();
+            assert_eq!(summary(&results), summary(&expected));
+        }
+        assert_eq!(engine.search_text(&text, "ERROR").unwrap().len(), 5);
+        assert_eq!(engine.get_cache_size(), 1);
+    }
+
+    #[test]
+    fn test_longer_queries_narrow_previous_matches() {
+        let text = "error: disk\nError: net\nterror\nno problem\nerror error\nerr";
+        let fresh = |pattern| SearchEngine::new(1000, false, false).unwrap().search_text(text, pattern).unwrap();
+        let summary = |results: Vec<SearchResult>| {
+            results
+                .iter()
+                .map(|r| (r.line_number, r.line.clone(), r.match_start))
+                .collect::<Vec<_>>()
+        };
+
+        let engine = SearchEngine::new(1000, false, false).unwrap();
+        for pattern in ["err", "erro", "ERROR", "error ", "prob", "problem"] {
+            let results = engine.search_text(text, pattern).unwrap();
+            assert_eq!(summary(results), summary(fresh(pattern)), "{}", pattern);
+        }
+
+        // A longer query on other text is searched in full
+        let engine = SearchEngine::new(1000, false, false).unwrap();
+        engine.search_text(text, "err").unwrap();
+        assert_eq!(engine.search_text("error here\n", "error").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_styled_search_filters_by_colour() {
+        let engine = SearchEngine::new(1000, true, false).unwrap();
+        let styled = crate::search::ansi::parse(
+            "build \x1b[31mfailed\x1b[0m\nfailed tests: 0\n\x1b[1;91mfailed\x1b[0m to link",
+        );
+        let red = StyleFilter::parse("red").unwrap();
+
+        let all = engine.search_styled(&styled, "failed", None).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].styles.len(), 1);
+
+        let results = engine.search_styled(&styled, "failed", Some(&red)).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].line_number, 1);
+        assert_eq!(results[0].match_start, 6);
+        assert_eq!(results[1].line_number, 3);
+    }
+
+    #[test]
+    fn test_search_windows_tags_results() {
+        let engine = SearchEngine::new(1000, true, false).unwrap();
+        let windows = vec![
+            WindowText { window_id: 4, tab_id: 1, text: "ok\npanic: boom\n".to_string() },
+            WindowText { window_id: 9, tab_id: 2, text: "nothing here\n".to_string() },
+            WindowText { window_id: 2, tab_id: 2, text: "panic: again\n".to_string() },
+        ];
+
+        let results = engine.search_windows(&windows, "panic").unwrap();
+        let found: Vec<_> = results
+            .iter()
+            .map(|r| (r.window_id, r.tab_id, r.line_number))
+            .collect();
+        assert_eq!(found, vec![(Some(4), Some(1), 2), (Some(2), Some(2), 1)]);
+    }
+
+    #[test]
+    fn test_byte_search_reports_offsets() {
+        let engine = SearchEngine::new(1000, true, false).unwrap();
+        let buffer = b"ok\nbad \xff byte: ERROR\nERROR ERROR\n";
+        let matches = engine.search_bytes(buffer, "ERROR").unwrap();
+
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].line_number, 2);
+        assert_eq!(&buffer[matches[0].bytes.clone()], b"ERROR");
+        assert_eq!(matches[0].line(buffer), "bad \u{fffd} byte: ERROR\n");
+        let result = matches[0].to_result(buffer);
+        assert_eq!(&result.line[result.match_start..result.match_end], "ERROR");
+        assert_eq!(matches[2].bytes, 27..32);
+
+        let result = matches[2].to_result(buffer);
+        assert_eq!((result.line_number, result.match_start, result.match_end), (3, 6, 11));
+    }
+
+    #[test]
+    fn test_parallel_search_matches_sequential() {
+        let mut text = String::new();
+        for i in 0..5000 {
+            text.push_str(&format!("line {} {}\n", i, if i % 7 == 0 { "ERROR ERROR" } else { "ok" }));
+        }
+        let sequential = SearchEngine::new(1000, true, false).unwrap();
+        let parallel = SearchEngine::new(1000, true, false)
+            .unwrap()
+            .with_parallel_search(0, 1000);
+
+        let expected = sequential.search_bytes(text.as_bytes(), "ERROR").unwrap();
+        assert_eq!(parallel.search_bytes(text.as_bytes(), "ERROR").unwrap(), expected);
+        assert_eq!(expected.len(), 2 * 715);
+        assert_eq!(expected[2].line_number, 8);
+
+        let results = parallel.search_text(&text, "ERROR").unwrap();
+        assert_eq!(results.last().unwrap().line, "line 4998 ERROR ERROR\n");
+        assert_eq!(results.last().unwrap().line_number, 4999);
+    }
+
+    #[test]
+    fn test_parallel_search_handles_binary_data_like_sequential() {
+        let mut text = String::new();
+        for i in 0..20000 {
+            let line = match i {
+                // Past the first 64KB, in a line that does not match
+                8000 => "raw \0 bytes",
+                15000 => "ERROR with \0 bytes",
+                _ if i % 7 == 0 => "ERROR",
+                _ => "ok",
+            };
+            text.push_str(&format!("line {} {}\n", i, line));
+        }
+        assert!(text.find('\0').unwrap() > BINARY_DETECTION_HEAD);
+        let sequential = SearchEngine::new(1000, true, false).unwrap();
+        let parallel = SearchEngine::new(1000, true, false)
+            .unwrap()
+            .with_parallel_search(0, 16 * 1024);
+
+        // Matches up to the matching line with binary data are kept
+        let expected = sequential.search_bytes(text.as_bytes(), "ERROR").unwrap();
+        assert_eq!(expected.len(), 2143);
+        assert_eq!(parallel.search_bytes(text.as_bytes(), "ERROR").unwrap(), expected);
+
+        // Binary data at the start ends the search before it begins
+        let text = format!("\0{}", text);
+        assert!(sequential.search_bytes(text.as_bytes(), "ERROR").unwrap().is_empty());
+        assert!(parallel.search_bytes(text.as_bytes(), "ERROR").unwrap().is_empty());
+    }
+
     #[test]
     fn test_buffer_search() {
         let engine = SearchEngine::new(1000, true, false).unwrap();
         let buffer = b"Hello world\nThis is a test\nHello again";
-        let results = engine.search_buffer(buffer, "Hello").unwrap();
+        let results: Vec<_> = engine
+            .search_bytes(buffer, "Hello")
+            .unwrap()
+            .iter()
+            .map(|mat| mat.to_result(buffer))
+            .collect();
         
         assert_eq!(results.len(), 2);
         assert_eq!(results[0].line_number, 1);
@@ -1,3 +1,5 @@
+pub mod ansi;
+pub mod blocks;
 pub mod engine;
 pub mod buffer;
 pub mod pattern;
@@ -0,0 +1,239 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::kitty::commands::Extent;
+use crate::kitty::KittyClient;
+use crate::search::ansi::{self, StyleFilter};
+use crate::search::blocks::{self, BlockFilter, CommandBlock};
+use crate::search::engine::SearchResult;
+use crate::search::SearchEngine;
+
+/// How long the query has to stay the same before it is searched.
+pub const SEARCH_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// What to search for and where, taken from the UI when the search starts.
+#[derive(Debug, Clone)]
+pub struct SearchRequest {
+    pub query: String,
+    pub extent: Extent,
+    pub target_window: Option<u32>,
+    pub all_windows: bool,
+    pub ansi: bool,
+    pub style_filter: Option<StyleFilter>,
+    pub command_blocks: bool,
+    pub block_filter: Option<BlockFilter>,
+    pub last_exit_status: Option<i32>,
+    /// Rows the target window is scrolled up by as the search starts
+    pub scrolled_by: usize,
+}
+
+/// The matches for one request, with what the UI needs to jump to them.
+#[derive(Debug, Default)]
+pub struct SearchOutcome {
+    pub results: Vec<SearchResult>,
+    /// Lines in the capture, when searching a single window
+    pub captured_lines: usize,
+    /// Lines in each window's capture, when searching all windows
+    pub window_lines: HashMap<u32, usize>,
+    /// Command blocks of the capture, when searching by command
+    pub blocks: Vec<CommandBlock>,
+    /// Rows the window was scrolled up by when captured, when searching a
+    /// single window; see `JumpTarget::scrolled_by`
+    pub scrolled_by: usize,
+}
+
+impl SearchRequest {
+    /// Captures the text from kitty, then searches it on the blocking pool so
+    /// a big buffer does not hold up the runtime.
+    pub async fn run(self, client: &KittyClient, engine: SearchEngine) -> Result<SearchOutcome> {
+        if self.all_windows {
+            let windows = client.get_all_window_contents(self.extent).await?;
+            return blocking(move || {
+                Ok(SearchOutcome {
+                    window_lines: windows
+                        .iter()
+                        .map(|window| (window.window_id, window.text.lines().count()))
+                        .collect(),
+                    results: engine.search_windows(&windows, &self.query)?,
+                    ..SearchOutcome::default()
+                })
+            })
+            .await;
+        }
+
+        if self.ansi {
+            let raw = client.get_ansi_content(self.target_window, self.extent).await?;
+            return blocking(move || self.search_styled(&engine, &raw)).await;
+        }
+
+        let text = client.get_buffer_content(self.target_window, self.extent).await?;
+        blocking(move || {
+            Ok(SearchOutcome {
+                captured_lines: text.lines().count(),
+                results: engine.search_text(&text, &self.query)?,
+                scrolled_by: self.scrolled_by,
+                ..SearchOutcome::default()
+            })
+        })
+        .await
+    }
+
+    fn search_styled(&self, engine: &SearchEngine, raw: &str) -> Result<SearchOutcome> {
+        let styled = ansi::parse(raw);
+        let mut outcome = SearchOutcome {
+            captured_lines: styled.text.lines().count(),
+            results: engine.search_styled(&styled, &self.query, self.style_filter.as_ref())?,
+            scrolled_by: self.scrolled_by,
+            ..SearchOutcome::default()
+        };
+        if self.command_blocks {
+            outcome.blocks = blocks::segment(&styled);
+            if let Some(status) = self.last_exit_status {
+                blocks::set_last_exit_status(&mut outcome.blocks, status);
+            }
+            blocks::assign(&mut outcome.results, &outcome.blocks, self.block_filter);
+        }
+        Ok(outcome)
+    }
+}
+
+async fn blocking<T, F>(search: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(search).await?
+}
+
+/// Runs searches on a tokio task, so a slow `get-text` or a huge buffer
+/// never blocks input or redraws.
+///
+/// Starting a search cancels the one in flight. Each search is numbered,
+/// and only the outcome of the latest is handed back, so results for an old
+/// query never replace those of a newer one. A search already running on
+/// the blocking pool cannot be stopped; it finishes and is ignored. That
+/// includes a `get-text` over the terminal, which keeps the UI from reading
+/// keys until kitty's reply is in (see `TtyGate`).
+pub struct BackgroundSearch {
+    client: KittyClient,
+    engine: SearchEngine,
+    debounce: Duration,
+    generation: u64,
+    pending: Option<JoinHandle<(u64, Result<SearchOutcome>)>>,
+}
+
+impl BackgroundSearch {
+    pub fn new(client: KittyClient, engine: SearchEngine) -> Self {
+        Self {
+            client,
+            engine,
+            debounce: SEARCH_DEBOUNCE,
+            generation: 0,
+            pending: None,
+        }
+    }
+
+    pub fn set_debounce(&mut self, debounce: Duration) {
+        self.debounce = debounce;
+    }
+
+    /// Searches for `request` once the debounce window has passed without
+    /// another search starting, or right away without `debounce`.
+    pub fn start(&mut self, request: SearchRequest, debounce: bool) {
+        self.cancel();
+        let generation = self.generation;
+        let delay = if debounce { self.debounce } else { Duration::ZERO };
+        let client = self.client.clone();
+        let engine = self.engine.clone();
+
+        self.pending = Some(tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            debug!("Searching for {:?}", request.query);
+            (generation, request.run(&client, engine).await)
+        }));
+    }
+
+    /// Drops the search in flight, if any.
+    pub fn cancel(&mut self) {
+        self.generation += 1;
+        if let Some(pending) = self.pending.take() {
+            pending.abort();
+        }
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Waits up to `timeout` for the search in flight. `None` if it is still
+    /// running, or there was nothing to wait for.
+    pub async fn wait(&mut self, timeout: Duration) -> Option<Result<SearchOutcome>> {
+        let pending = self.pending.as_mut()?;
+        let joined = tokio::time::timeout(timeout, pending).await.ok()?;
+        self.pending = None;
+        self.outcome(joined)
+    }
+
+    /// Waits for the search in flight to finish.
+    pub async fn finish(&mut self) -> Option<Result<SearchOutcome>> {
+        let joined = self.pending.take()?.await;
+        self.outcome(joined)
+    }
+
+    fn outcome(
+        &self,
+        joined: Result<(u64, Result<SearchOutcome>), tokio::task::JoinError>,
+    ) -> Option<Result<SearchOutcome>> {
+        match joined {
+            Ok((generation, outcome)) if generation == self.generation => Some(outcome),
+            Ok(_) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kitty::fake::FakeKitty;
+
+    fn request(query: &str) -> SearchRequest {
+        SearchRequest {
+            query: query.to_string(),
+            extent: Extent::All,
+            target_window: None,
+            all_windows: false,
+            ansi: false,
+            style_filter: None,
+            command_blocks: false,
+            block_filter: None,
+            last_exit_status: None,
+            scrolled_by: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_newer_search_replaces_stalled_one() {
+        let kitty = FakeKitty::new()
+            .with_text("disk full\nok\n")
+            .with_stall("get-text", 1);
+        let engine = SearchEngine::new(1000, true, false).unwrap();
+        let mut search = BackgroundSearch::new(KittyClient::with_transport(kitty.clone()), engine);
+
+        // kitty never answers the first capture
+        search.start(request("disk"), false);
+        assert!(search.wait(Duration::from_millis(20)).await.is_none());
+        assert!(search.is_searching());
+
+        search.start(request("ok"), true);
+        let outcome = search.finish().await.unwrap().unwrap();
+        assert_eq!(outcome.results.len(), 1);
+        assert_eq!(outcome.results[0].line, "ok\n");
+        assert_eq!(outcome.captured_lines, 2);
+        assert_eq!(kitty.calls_to("get-text").len(), 2);
+        assert!(!search.is_searching());
+    }
+}
@@ -0,0 +1,161 @@
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Result as IoResult};
+use std::time::{Duration, Instant};
+
+use crate::kitty::transport::TtyGate;
+use crate::ui::screen::Screen;
+
+/// Longest stretch input is polled for at a time. The terminal is handed
+/// to waiting remote-control exchanges in between, so this is how long
+/// they may have to wait for it.
+const INPUT_SLICE: Duration = Duration::from_millis(10);
+
+/// Source of terminal events driving `SearchUI::run`.
+pub trait EventSource: Send {
+    fn next_event(&mut self, timeout: Duration) -> IoResult<Option<Event>>;
+
+    /// Whether another event is already waiting, e.g. because the user is
+    /// typing faster than we search.
+    fn has_pending(&mut self) -> IoResult<bool>;
+}
+
+/// Reads events from the real terminal.
+///
+/// kitty answers commands sent over the terminal on the same terminal, so
+/// input is only read when `TtyGate` says no exchange needs it.
+pub struct TerminalEvents;
+
+impl EventSource for TerminalEvents {
+    fn next_event(&mut self, timeout: Duration) -> IoResult<Option<Event>> {
+        poll_input(TtyGate::get(), timeout, Screen::poll_event)
+    }
+
+    fn has_pending(&mut self) -> IoResult<bool> {
+        match TtyGate::get().input() {
+            Some(_turn) => crossterm::event::poll(Duration::ZERO),
+            None => Ok(false),
+        }
+    }
+}
+
+/// Polls for input with `poll` for up to `timeout`, a slice at a time, only
+/// while `gate` lets input have the terminal.
+fn poll_input<T>(
+    gate: &TtyGate,
+    timeout: Duration,
+    mut poll: impl FnMut(Duration) -> IoResult<Option<T>>,
+) -> IoResult<Option<T>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let slice = INPUT_SLICE.min(deadline.saturating_duration_since(Instant::now()));
+        match gate.input() {
+            Some(_turn) => {
+                if let Some(input) = poll(slice)? {
+                    return Ok(Some(input));
+                }
+            }
+            None => std::thread::sleep(slice),
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+    }
+}
+
+/// Replays a fixed list of events, for driving the UI in tests.
+///
+/// Running out of events is reported as `UnexpectedEof` so a script that
+/// never exits the UI fails instead of hanging.
+#[derive(Default)]
+#[allow(dead_code)]
+pub struct ScriptedEvents {
+    events: VecDeque<Step>,
+}
+
+enum Step {
+    Event(Event),
+    Idle(Duration),
+}
+
+#[allow(dead_code)]
+impl ScriptedEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key(self, code: KeyCode) -> Self {
+        self.event(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)))
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        for c in text.chars() {
+            self = self.key(KeyCode::Char(c));
+        }
+        self
+    }
+
+    pub fn event(mut self, event: Event) -> Self {
+        self.events.push_back(Step::Event(event));
+        self
+    }
+
+    /// Blocks for `duration` and reports no event, like a poll timing out.
+    pub fn idle(mut self, duration: Duration) -> Self {
+        self.events.push_back(Step::Idle(duration));
+        self
+    }
+}
+
+impl EventSource for ScriptedEvents {
+    fn next_event(&mut self, _timeout: Duration) -> IoResult<Option<Event>> {
+        match self.events.pop_front() {
+            Some(Step::Event(event)) => Ok(Some(event)),
+            Some(Step::Idle(duration)) => {
+                std::thread::sleep(duration);
+                Ok(None)
+            }
+            None => Err(Error::new(ErrorKind::UnexpectedEof, "event script exhausted")),
+        }
+    }
+
+    /// Events scripted back to back count as typed ahead; an `idle` step
+    /// in between is a pause.
+    fn has_pending(&mut self) -> IoResult<bool> {
+        Ok(matches!(self.events.front(), Some(Step::Event(_))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_input_is_not_polled_during_tty_exchange() {
+        static GATE: TtyGate = TtyGate::new();
+        let exchanging = Arc::new(AtomicBool::new(false));
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+
+        // A background search reading kitty's reply on the blocking pool
+        let flag = Arc::clone(&exchanging);
+        let search = std::thread::spawn(move || {
+            let _turn = GATE.exchange();
+            flag.store(true, Ordering::SeqCst);
+            started_tx.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            flag.store(false, Ordering::SeqCst);
+        });
+        started_rx.recv().unwrap();
+
+        let mut polls = 0;
+        let event = poll_input(&GATE, Duration::from_secs(1), |_| {
+            polls += 1;
+            assert!(!exchanging.load(Ordering::SeqCst), "polled while kitty was replying");
+            Ok(Some(polls))
+        });
+        assert_eq!(event.unwrap(), Some(1));
+        search.join().unwrap();
+    }
+}
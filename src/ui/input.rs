@@ -1,4 +1,4 @@
-use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use anyhow::Result;
 
 #[allow(dead_code)]
@@ -22,6 +22,12 @@ impl InputHandler {
         }
 
         match key.code {
+            KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                Ok(InputAction::CycleExtent)
+            }
+            KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                Ok(InputAction::Pin)
+            }
             KeyCode::Char(c) => {
                 self.query.insert(self.cursor_pos, c);
                 self.cursor_pos += 1;
@@ -94,6 +100,12 @@ impl InputHandler {
     }
 }
 
+impl Default for InputHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, PartialEq)]
 #[allow(dead_code)]
 pub enum InputAction {
@@ -101,6 +113,8 @@ pub enum InputAction {
     QueryChanged,
     NavigateUp,
     NavigateDown,
+    CycleExtent,
+    Pin,
     Select,
     Exit,
 }
\ No newline at end of file
@@ -1,5 +1,8 @@
+pub mod background;
 pub mod overlay;
+pub mod events;
 pub mod input;
+pub mod preview;
 pub mod renderer;
 pub mod screen;
 
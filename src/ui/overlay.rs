@@ -1,33 +1,121 @@
 use anyhow::Result;
 use crossterm::event::{Event, KeyEvent, KeyEventKind};
+use std::collections::HashMap;
+use std::io::Write;
 use std::time::Duration;
+use tracing::debug;
 
+use crate::pins::PinStore;
+use crate::search::ansi::StyleFilter;
+use crate::search::blocks::{BlockFilter, CommandBlock};
+use crate::search::engine::SearchResult;
 use crate::search::SearchEngine;
+use crate::kitty::commands::Extent;
+use crate::kitty::error;
+use crate::kitty::marker::{MarkerSpec, PinnedTerm, MARKER_GROUPS};
+use crate::kitty::scroll::{JumpPosition, JumpTarget};
 use crate::kitty::KittyClient;
+use crate::ui::background::{BackgroundSearch, SearchOutcome, SearchRequest};
+use crate::ui::events::{EventSource, TerminalEvents};
+use crate::ui::preview::PreviewScroller;
 use crate::ui::screen::Screen;
 use crate::ui::input::{InputHandler, InputAction};
 
+/// How long to wait on a running search before checking for keys again.
+const SEARCH_POLL: Duration = Duration::from_millis(20);
+
 pub struct SearchUI {
     kitty_client: KittyClient,
     search_engine: SearchEngine,
+    search: BackgroundSearch,
     screen: Screen,
+    events: Box<dyn EventSource>,
     input: InputHandler,
-    results: Vec<crate::search::engine::SearchResult>,
+    target_window: Option<u32>,
+    extent: Extent,
+    ansi: bool,
+    style_filter: Option<StyleFilter>,
+    command_blocks: bool,
+    block_filter: Option<BlockFilter>,
+    blocks: Vec<CommandBlock>,
+    last_exit_status: Option<i32>,
+    jump_position: JumpPosition,
+    scroll_origin: u32,
+    preview: Option<PreviewScroller>,
+    captured_lines: usize,
+    /// Rows the target window was scrolled up by when it was captured
+    captured_scrolled_by: usize,
+    pin_store: PinStore,
+    pins: Vec<PinnedTerm>,
+    keep_markers: bool,
+    all_windows: bool,
+    window_lines: HashMap<u32, usize>,
+    marked_window: Option<u32>,
+    /// The last failure, with a hint on fixing it, shown until the next key
+    error: Option<(String, Option<String>)>,
+    results: Vec<SearchResult>,
     current_idx: usize,
     dirty: bool,
 }
 
 impl SearchUI {
     pub async fn new(kitty_client: KittyClient, search_engine: SearchEngine) -> Result<Self> {
-        Ok(Self {
+        Ok(Self::with_io(kitty_client, search_engine, Screen::new()?, Box::new(TerminalEvents)))
+    }
+
+    /// Builds a UI that draws into `output` and reads from `events` instead of
+    /// the terminal, so the full `run` loop can be exercised without a TTY.
+    #[allow(dead_code)]
+    pub fn headless<E, W>(
+        kitty_client: KittyClient,
+        search_engine: SearchEngine,
+        events: E,
+        output: W,
+    ) -> Self
+    where
+        E: EventSource + 'static,
+        W: Write + Send + 'static,
+    {
+        Self::with_io(kitty_client, search_engine, Screen::headless(output), Box::new(events))
+    }
+
+    fn with_io(
+        kitty_client: KittyClient,
+        search_engine: SearchEngine,
+        screen: Screen,
+        events: Box<dyn EventSource>,
+    ) -> Self {
+        Self {
+            search: BackgroundSearch::new(kitty_client.clone(), search_engine.clone()),
             kitty_client,
             search_engine,
-            screen: Screen::new()?,
+            screen,
+            events,
             input: InputHandler::new(),
+            target_window: None,
+            extent: Extent::default(),
+            ansi: false,
+            style_filter: None,
+            command_blocks: false,
+            block_filter: None,
+            blocks: Vec::new(),
+            last_exit_status: None,
+            jump_position: JumpPosition::default(),
+            scroll_origin: 0,
+            preview: None,
+            captured_lines: 0,
+            captured_scrolled_by: 0,
+            pin_store: PinStore::default(),
+            pins: Vec::new(),
+            keep_markers: false,
+            all_windows: false,
+            window_lines: HashMap::new(),
+            marked_window: None,
+            error: None,
             results: Vec::new(),
             current_idx: 0,
             dirty: true,
-        })
+        }
     }
 
     pub fn set_initial_query(&mut self, query: String) {
@@ -35,40 +123,135 @@ impl SearchUI {
         self.dirty = true;
     }
 
+    /// Which part of the window's text to search; can be cycled with Ctrl+E.
+    pub fn set_extent(&mut self, extent: Extent) {
+        self.extent = extent;
+        self.dirty = true;
+    }
+
+    /// Captures the buffer with its colours so matches carry their styles.
+    pub fn set_ansi(&mut self, ansi: bool) {
+        self.ansi = ansi;
+    }
+
+    /// Only keeps matches drawn in the given style; implies ANSI capture.
+    pub fn set_style_filter(&mut self, filter: Option<StyleFilter>) {
+        self.ansi |= filter.is_some();
+        self.style_filter = filter;
+    }
+
+    /// Splits the buffer into commands using shell integration marks, so
+    /// each match shows the command it belongs to; implies ANSI capture,
+    /// which keeps the marks.
+    pub fn set_command_blocks(&mut self, enabled: bool) {
+        self.command_blocks = enabled;
+        self.ansi |= enabled;
+    }
+
+    /// Only keeps matches in command lines, outputs, or outputs of failed
+    /// commands; implies command blocks.
+    pub fn set_block_filter(&mut self, filter: Option<BlockFilter>) {
+        self.set_command_blocks(self.command_blocks || filter.is_some());
+        self.block_filter = filter;
+    }
+
+    /// Where in the viewport the selected match is placed when jumping to it.
+    pub fn set_jump_position(&mut self, position: JumpPosition) {
+        self.jump_position = position;
+    }
+
+    /// How many lines the target window was scrolled up by when the search
+    /// started, so abandoning the search can scroll it back there.
+    pub fn set_scroll_origin(&mut self, lines: u32) {
+        self.scroll_origin = lines;
+    }
+
+    /// Where pinned terms are loaded from and saved to; by default they are
+    /// only kept in memory.
+    pub fn set_pin_store(&mut self, store: PinStore) {
+        self.pin_store = store;
+    }
+
+    /// Leaves pinned terms highlighted when the UI exits.
+    pub fn set_keep_markers(&mut self, keep: bool) {
+        self.keep_markers = keep;
+    }
+
+    /// Searches every window but our own instead of a single one.
+    pub fn set_all_windows(&mut self, all_windows: bool) {
+        self.all_windows = all_windows;
+    }
+
+    /// How long the query has to stay the same before it is searched.
+    pub fn set_debounce(&mut self, debounce: Duration) {
+        self.search.set_debounce(debounce);
+    }
+
+    /// Searches `window_id` instead of the window the UI runs in.
+    pub fn set_target_window(&mut self, window_id: Option<u32>) {
+        self.target_window = window_id;
+    }
+
     pub async fn run(&mut self) -> Result<()> {
+        self.fit_capabilities();
+        if self.kitty_client.capabilities().scroll_window {
+            self.preview = Some(PreviewScroller::new(
+                self.kitty_client.clone(),
+                self.target_window,
+                self.scroll_origin,
+            ));
+        }
+        self.pins = self.pin_store.pins(self.target_window).to_vec();
+        if self.command_blocks {
+            self.last_exit_status = self.last_exit_status().await;
+        }
+
         // Initial search if query is set
         if !self.input.query().is_empty() {
-            self.recompute_matches().await?;
+            self.start_search(false).await?;
         }
 
         loop {
             // Redraw if needed
             if self.dirty {
-                self.screen.draw_panel(
-                    self.input.query(),
-                    self.current_idx + 1,
-                    self.results.len(),
-                )?;
+                let pinned: Vec<&str> = self.pins.iter().map(|pin| pin.query.as_str()).collect();
+                let searched = self.searched_label();
+                let error = self
+                    .error
+                    .as_ref()
+                    .map(|(message, hint)| (message.as_str(), hint.as_deref()));
+                let matches = (!self.search.is_searching())
+                    .then_some((self.current_idx + 1, self.results.len()));
+                self.screen.draw_panel(self.input.query(), matches, &searched, &pinned, error)?;
                 self.dirty = false;
             }
 
+            // Let a search finish while no keys are waiting, looking for
+            // keys in between so typing never waits on it
+            if self.search.is_searching() && !self.events.has_pending()? {
+                if let Some(outcome) = self.search.wait(SEARCH_POLL).await {
+                    if let Err(e) = self.apply_search(outcome).await {
+                        self.show_error(e);
+                    }
+                }
+                continue;
+            }
+
             // Wait for next event
-            if let Some(evt) = Screen::poll_event(Duration::from_millis(400))? {
+            if let Some(evt) = self.events.next_event(Duration::from_millis(400))? {
                 match evt {
-                    Event::Key(key) => {
-                        if let Some(action) = self.handle_key(key).await? {
-                            match action {
-                                InputAction::Exit => break,
-                                InputAction::Select => {
-                                    if let Some(result) = self.results.get(self.current_idx) {
-                                        self.kitty_client.jump_to_line(result.line_number).await?;
-                                    }
-                                    break;
-                                }
-                                _ => {}
-                            }
+                    Event::Key(key) => match self.handle_key(key).await {
+                        Ok(Some(InputAction::Exit)) => {
+                            self.restore_scroll().await;
+                            break;
                         }
-                    }
+                        Ok(Some(InputAction::Select)) => match self.jump_to_current().await {
+                            Ok(()) => break,
+                            Err(e) => self.show_error(e),
+                        },
+                        Ok(_) => {}
+                        Err(e) => self.show_error(e),
+                    },
                     Event::Resize(_, _) => self.dirty = true,
                     _ => {}
                 }
@@ -76,7 +259,20 @@ impl SearchUI {
         }
 
         // Cleanup
-        self.remove_marker().await;
+        self.search.cancel();
+        if !self.kitty_client.capabilities().markers {
+            return Ok(());
+        }
+        if self.keep_markers && !self.pins.is_empty() {
+            let spec = MarkerSpec::for_pins(&self.pins);
+            let _ = self.kitty_client.create_marker(self.target_window, &spec).await;
+            // Pins belong to the target window; other windows lose the search
+            if self.all_windows && self.marked_window.is_some() && self.marked_window != self.target_window {
+                let _ = self.kitty_client.remove_marker(self.marked_window).await;
+            }
+        } else {
+            self.remove_marker().await;
+        }
         Ok(())
     }
 
@@ -85,55 +281,310 @@ impl SearchUI {
             return Ok(None);
         }
 
+        if self.error.take().is_some() {
+            self.dirty = true;
+        }
+
         let action = self.input.handle_key_event(key)?;
         match action {
-            InputAction::QueryChanged => {
-                self.recompute_matches().await?;
+            InputAction::QueryChanged => self.start_search(true).await?,
+            InputAction::CycleExtent => {
+                self.extent = self.kitty_client.capabilities().next_extent(self.extent);
+                self.start_search(false).await?;
+            }
+            // These act on the matches for the query as typed
+            InputAction::Select
+            | InputAction::Pin
+            | InputAction::NavigateUp
+            | InputAction::NavigateDown => self.finish_search().await?,
+            InputAction::None | InputAction::Exit => {}
+        }
+
+        match action {
+            InputAction::Pin if !self.input.query().is_empty() => {
+                self.toggle_pin()?;
+                self.refresh_marker().await?;
                 self.dirty = true;
             }
-            InputAction::NavigateUp => {
-                if self.current_idx > 0 {
-                    self.current_idx -= 1;
-                    self.dirty = true;
-                }
+            InputAction::NavigateUp if self.current_idx > 0 => {
+                self.current_idx -= 1;
+                self.refresh_marker().await?;
+                self.preview_current();
+                self.dirty = true;
             }
-            InputAction::NavigateDown => {
-                if self.current_idx + 1 < self.results.len() {
-                    self.current_idx += 1;
-                    self.dirty = true;
-                }
+            InputAction::NavigateDown if self.current_idx + 1 < self.results.len() => {
+                self.current_idx += 1;
+                self.refresh_marker().await?;
+                self.preview_current();
+                self.dirty = true;
             }
             _ => {}
         }
         Ok(Some(action))
     }
 
-    async fn recompute_matches(&mut self) -> Result<()> {
-        self.remove_marker().await;
-        
+    /// Searches for the query in the background, dropping any search for
+    /// an earlier one. An empty query clears the matches right away.
+    async fn start_search(&mut self, debounce: bool) -> Result<()> {
+        self.dirty = true;
         if self.input.query().is_empty() {
+            self.search.cancel();
             self.results.clear();
             self.current_idx = 0;
-            return Ok(());
+            return self.refresh_marker().await;
+        }
+
+        let request = SearchRequest {
+            query: self.input.query().to_string(),
+            extent: self.extent,
+            target_window: self.target_window,
+            all_windows: self.all_windows,
+            ansi: self.ansi,
+            style_filter: self.style_filter.clone(),
+            command_blocks: self.command_blocks,
+            block_filter: self.block_filter,
+            last_exit_status: self.last_exit_status,
+            scrolled_by: match &self.preview {
+                Some(preview) => preview.scrolled_by(),
+                None => self.scroll_origin as usize,
+            },
+        };
+        self.search.start(request, debounce);
+        Ok(())
+    }
+
+    /// Waits for the search in flight, if any, and shows its matches.
+    async fn finish_search(&mut self) -> Result<()> {
+        match self.search.finish().await {
+            Some(outcome) => self.apply_search(outcome).await,
+            None => Ok(()),
         }
+    }
 
-        let buffer_content = self.kitty_client.get_buffer_content().await?;
-        self.results = self.search_engine.search_text(&buffer_content, self.input.query())?;
+    async fn apply_search(&mut self, outcome: Result<SearchOutcome>) -> Result<()> {
+        self.dirty = true;
+        let outcome = outcome?;
+        self.results = outcome.results;
+        self.captured_lines = outcome.captured_lines;
+        self.captured_scrolled_by = outcome.scrolled_by;
+        self.window_lines = outcome.window_lines;
+        self.blocks = outcome.blocks;
         self.current_idx = 0;
-        
-        if !self.results.is_empty() {
-            self.refresh_marker().await?;
+        self.refresh_marker().await
+    }
+
+    /// Leaves out what the connected kitty cannot do, saying so in the
+    /// panel, rather than failing on the first command that needs it.
+    fn fit_capabilities(&mut self) {
+        let capabilities = *self.kitty_client.capabilities();
+        let mut missing = Vec::new();
+        if !capabilities.supports_extent(self.extent) {
+            missing.push(format!("the {} extent", self.extent.as_str()));
+            self.extent = Extent::All;
+        }
+        if self.ansi && !capabilities.ansi_text {
+            missing.push("--ansi".to_string());
+            self.ansi = false;
+            self.style_filter = None;
+            self.command_blocks = false;
+            self.block_filter = None;
+        }
+        if !capabilities.markers {
+            missing.push("highlighting".to_string());
+        }
+        if !capabilities.scroll_window {
+            missing.push("jumping".to_string());
         }
 
+        if !missing.is_empty() {
+            let message = format!("kitty {} lacks {}", capabilities, missing.join(", "));
+            debug!("{}", message);
+            self.error = Some((message, Some("update kitty".to_string())));
+            self.dirty = true;
+        }
+    }
+
+    /// Keeps the UI up after a failure, showing what went wrong in the panel.
+    fn show_error(&mut self, error: anyhow::Error) {
+        let hint = error::hint_for(&error);
+        self.error = Some((error.to_string(), hint));
+        self.dirty = true;
+    }
+
+    /// Pins the query to a marker group of its own, or unpins it if it
+    /// already is. With all groups taken, the oldest pin is replaced.
+    fn toggle_pin(&mut self) -> Result<()> {
+        let pin = PinnedTerm {
+            group: 0,
+            query: self.input.query().to_string(),
+            case_sensitive: self.search_engine.case_sensitive(),
+            regex: self.search_engine.regex_enabled(),
+        };
+
+        if let Some(pos) = self.pins.iter().position(|p| p.same_term(&pin)) {
+            self.pins.remove(pos);
+        } else {
+            let free = MARKER_GROUPS
+                .into_iter()
+                .find(|group| self.pins.iter().all(|p| p.group != *group));
+            let group = match free {
+                Some(group) => group,
+                None => self.pins.remove(0).group,
+            };
+            self.pins.push(PinnedTerm { group, ..pin });
+        }
+
+        self.pin_store.set_pins(self.target_window, self.pins.clone());
+        self.pin_store.save()
+    }
+
+    /// Scrolls to the selected match, first focusing its window when
+    /// searching all windows.
+    async fn jump_to_current(&mut self) -> Result<()> {
+        if let Some(preview) = self.preview.as_mut() {
+            preview.cancel().await;
+        }
+        let Some(result) = self.results.get(self.current_idx) else {
+            return Ok(());
+        };
+
+        // How far other windows are scrolled up is not known
+        let (window_id, captured_lines, scrolled_by) = match result.window_id {
+            Some(id) => (Some(id), self.window_lines.get(&id).copied().unwrap_or_default(), 0),
+            None => (self.target_window, self.captured_lines, self.captured_scrolled_by),
+        };
+        if let Some(id) = result.window_id {
+            self.kitty_client.focus_window(id).await?;
+        }
+        if !self.kitty_client.capabilities().scroll_window {
+            return Ok(());
+        }
+
+        let target = JumpTarget {
+            extent: self.extent,
+            captured_lines,
+            line_number: result.line_number,
+            line: &result.line,
+            scrolled_by,
+        };
+        self.kitty_client
+            .jump_to_line(window_id, &target, self.jump_position)
+            .await?;
         Ok(())
     }
 
-    async fn refresh_marker(&self) -> Result<()> {
-        self.kitty_client.create_text_marker(self.input.query()).await
+    /// Scrolls the target window to the highlighted match in the background.
+    /// Not done when searching all windows, where the match is usually in a
+    /// window that is not visible.
+    fn preview_current(&mut self) {
+        if self.all_windows {
+            return;
+        }
+        let (Some(preview), Some(result)) = (self.preview.as_mut(), self.results.get(self.current_idx)) else {
+            return;
+        };
+        preview.preview(
+            self.extent,
+            self.captured_lines,
+            self.captured_scrolled_by,
+            result.line_number,
+            result.line.clone(),
+            self.jump_position,
+        );
+    }
+
+    async fn restore_scroll(&mut self) {
+        if let Some(preview) = self.preview.as_mut() {
+            let _ = preview.restore().await;
+        }
+    }
+
+    /// Highlights all matches, the current one in a group of its own, and
+    /// the pinned terms. Searching all windows, the highlights follow the
+    /// window of the selected match.
+    async fn refresh_marker(&mut self) -> Result<()> {
+        if !self.kitty_client.capabilities().markers {
+            return Ok(());
+        }
+        let current = self.results.get(self.current_idx);
+        let window_id = current.and_then(|result| result.window_id).or(self.target_window);
+
+        if self.all_windows && self.marked_window.is_some() && self.marked_window != window_id {
+            let _ = self.kitty_client.remove_marker(self.marked_window).await;
+        }
+        self.marked_window = window_id;
+
+        let pins: &[PinnedTerm] = if window_id == self.target_window { &self.pins } else { &[] };
+        let spec = match current {
+            Some(result) => MarkerSpec::for_search(
+                self.input.query(),
+                self.search_engine.case_sensitive(),
+                self.search_engine.regex_enabled(),
+            )
+            .with_current(&result.line, result.match_start..result.match_end)
+            .with_pins(pins),
+            None => MarkerSpec::for_pins(pins),
+        };
+
+        if spec.is_empty() {
+            let _ = self.kitty_client.remove_marker(window_id).await;
+            Ok(())
+        } else {
+            self.kitty_client.create_marker(window_id, &spec).await
+        }
+    }
+
+    /// kitty keeps prompt and output marks in the scrollback but not exit
+    /// statuses, so the status of the last command comes from `ls`.
+    async fn last_exit_status(&self) -> Option<i32> {
+        let tree = match self.kitty_client.list_windows().await {
+            Ok(tree) => tree,
+            Err(e) => {
+                debug!("No exit status for the last command: {}", e);
+                return None;
+            }
+        };
+        let window = match self.target_window {
+            Some(id) => tree.window(id),
+            None => tree.self_window(),
+        }?;
+        window.at_prompt.then_some(window.last_cmd_exit_status)
+    }
+
+    /// What is searched, for the panel: the extent, and when searching all
+    /// windows or by command, where the selected match is.
+    fn searched_label(&self) -> String {
+        if !self.all_windows {
+            let current = self.results.get(self.current_idx);
+            let block = current
+                .and_then(|result| result.command_index)
+                .and_then(|idx| self.blocks.get(idx));
+            return match block {
+                Some(block) if block.failed() => format!(
+                    "{}, `{}` (exit {})",
+                    self.extent.as_str(),
+                    block.command,
+                    block.exit_status.unwrap_or_default()
+                ),
+                Some(block) => format!("{}, `{}`", self.extent.as_str(), block.command),
+                None => self.extent.as_str().to_string(),
+            };
+        }
+        match self.results.get(self.current_idx) {
+            Some(SearchResult {
+                window_id: Some(window_id),
+                tab_id: Some(tab_id),
+                ..
+            }) => format!("{}, tab {} window {}", self.extent.as_str(), tab_id, window_id),
+            _ => format!("{} of all windows", self.extent.as_str()),
+        }
     }
 
     async fn remove_marker(&self) {
-        let _ = self.kitty_client.remove_marker().await;
+        let _ = self.kitty_client.remove_marker(self.target_window).await;
+        if self.all_windows && self.marked_window != self.target_window {
+            let _ = self.kitty_client.remove_marker(self.marked_window).await;
+        }
     }
 }
 
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::kitty::commands::{Extent, ScrollAmount};
+use crate::kitty::scroll::{JumpPosition, JumpTarget};
+use crate::kitty::KittyClient;
+
+/// Minimum time between two preview scrolls.
+pub const PREVIEW_INTERVAL: Duration = Duration::from_millis(75);
+
+/// Scrolls the target window to the highlighted match while navigating, and
+/// back to where it was if the search is abandoned.
+///
+/// Requests are rate limited: each one waits until `PREVIEW_INTERVAL` after
+/// the last scroll sent, and one that is superseded before then is dropped,
+/// so holding an arrow key only sends the positions kitty has time to show.
+/// A scroll that has started is always allowed to finish, so commands never
+/// interleave. Over the terminal, they also take turns with reading keys
+/// (see `TtyGate`).
+pub struct PreviewScroller {
+    client: KittyClient,
+    window_id: Option<u32>,
+    origin: u32,
+    generation: Arc<AtomicU64>,
+    scrolled: Arc<AtomicBool>,
+    /// Rows the window is scrolled up by, as of the last preview shown
+    offset: Arc<AtomicUsize>,
+    /// When the last scroll went out; dropped previews do not count
+    last_sent: Arc<Mutex<Option<Instant>>>,
+    pending: Option<JoinHandle<()>>,
+}
+
+impl PreviewScroller {
+    /// `origin` is how many lines `window_id` was scrolled up by when the
+    /// search started.
+    pub fn new(client: KittyClient, window_id: Option<u32>, origin: u32) -> Self {
+        Self {
+            client,
+            window_id,
+            origin,
+            generation: Arc::new(AtomicU64::new(0)),
+            scrolled: Arc::new(AtomicBool::new(false)),
+            offset: Arc::new(AtomicUsize::new(origin as usize)),
+            last_sent: Arc::new(Mutex::new(None)),
+            pending: None,
+        }
+    }
+
+    /// Schedules a scroll to `line_number` of a capture of `extent`, taken
+    /// while the window was scrolled up by `scrolled_by` rows, replacing any
+    /// scroll that has not been sent yet.
+    pub fn preview(
+        &mut self,
+        extent: Extent,
+        captured_lines: usize,
+        scrolled_by: usize,
+        line_number: u64,
+        line: String,
+        position: JumpPosition,
+    ) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        let previous = self.pending.take();
+        let client = self.client.clone();
+        let window_id = self.window_id;
+        let current = Arc::clone(&self.generation);
+        let scrolled = Arc::clone(&self.scrolled);
+        let offset = Arc::clone(&self.offset);
+        let last_sent = Arc::clone(&self.last_sent);
+
+        self.pending = Some(tokio::spawn(async move {
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+            let slot = last_sent.lock().unwrap().map(|sent| sent + PREVIEW_INTERVAL);
+            if let Some(slot) = slot {
+                tokio::time::sleep_until(slot).await;
+            }
+            if current.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            *last_sent.lock().unwrap() = Some(Instant::now());
+
+            let target = JumpTarget {
+                extent,
+                captured_lines,
+                line_number,
+                line: &line,
+                scrolled_by,
+            };
+            scrolled.store(true, Ordering::SeqCst);
+            match client.jump_to_line(window_id, &target, position).await {
+                Ok(rows) => offset.store(rows, Ordering::SeqCst),
+                Err(e) => debug!("Preview scroll failed: {}", e),
+            }
+        }));
+    }
+
+    /// Rows the window is scrolled up by now: where it was when the search
+    /// started until a preview has been shown.
+    pub fn scrolled_by(&self) -> usize {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    /// Drops any scroll that has not been sent yet and waits for one in
+    /// flight to finish.
+    pub async fn cancel(&mut self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        if let Some(pending) = self.pending.take() {
+            let _ = pending.await;
+        }
+    }
+
+    /// Cancels pending previews and, if any were shown, scrolls the window
+    /// back to where it was when the search started.
+    pub async fn restore(&mut self) -> Result<()> {
+        self.cancel().await;
+        self.offset.store(self.origin as usize, Ordering::SeqCst);
+        if !self.scrolled.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        self.client.scroll(self.window_id, ScrollAmount::End).await?;
+        if self.origin > 0 {
+            self.client
+                .scroll(self.window_id, ScrollAmount::Lines(-(self.origin as i64)))
+                .await?;
+        }
+        Ok(())
+    }
+}
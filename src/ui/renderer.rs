@@ -6,6 +6,12 @@ pub struct UIRenderer {
     pub max_results_displayed: usize,
 }
 
+impl Default for UIRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[allow(dead_code)]
 impl UIRenderer {
     pub fn new() -> Self {
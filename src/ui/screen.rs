@@ -5,11 +5,15 @@ use crossterm::{
   terminal::{self, Clear, ClearType},
   ExecutableCommand, QueueableCommand,
 };
-use std::io::{stdout, Stdout, Write, Result as IoResult};
+use std::io::{stdout, Write, Result as IoResult};
 use std::time::Duration;
 
+/// Size assumed for headless screens, which have no terminal to query.
+const HEADLESS_SIZE: (u16, u16) = (80, 24);
+
 pub struct Screen {
-  out: Stdout,
+  out: Box<dyn Write + Send>,
+  headless: bool,
 }
 
 impl Screen {
@@ -17,11 +21,33 @@ impl Screen {
     let mut out = stdout();
     terminal::enable_raw_mode()?;
     out.execute(Hide)?;
-    Ok(Self { out })
+    Ok(Self {
+      out: Box::new(out),
+      headless: false,
+    })
   }
 
-  pub fn draw_panel(&mut self, query: &str, idx: usize, total: usize) -> IoResult<()> {
-    let (cols, rows) = terminal::size()?;
+  /// A screen that renders into `out` without touching the terminal mode.
+  pub fn headless<W: Write + Send + 'static>(out: W) -> Self {
+    Self {
+      out: Box::new(out),
+      headless: true,
+    }
+  }
+
+  pub fn draw_panel(
+    &mut self,
+    query: &str,
+    matches: Option<(usize, usize)>,
+    extent: &str,
+    pinned: &[&str],
+    error: Option<(&str, Option<&str>)>,
+  ) -> IoResult<()> {
+    let (cols, rows) = if self.headless {
+      HEADLESS_SIZE
+    } else {
+      terminal::size()?
+    };
     let x = cols.saturating_sub(30);
     let y = rows.saturating_sub(4);
 
@@ -41,14 +67,35 @@ impl Screen {
       .queue(Print(query.bold()))?
       .queue(Print("▌"))?;
 
-    // Second line: blank separator
+    // Second line: what part of the window is searched, or what went wrong
     self.out.queue(MoveTo(x, y + 1))?;
+    match error {
+      Some((message, _)) => self.out.queue(Print(message.red()))?,
+      None => self.out.queue(Print(format!("in {}  ^E change", extent).dim()))?,
+    };
 
-    // Third line: status
+    // Third line: status; no matches yet while the query is searched
+    let status = match matches {
+      Some((idx, total)) => format!("{}/{}", idx, total),
+      None => "searching…".to_string(),
+    };
     self
       .out
       .queue(MoveTo(x, y + 2))?
-      .queue(Print(format!("{}/{}  ↑↓ jump  Esc quit", idx, total)))?;
+      .queue(Print(format!("{}  ↑↓ jump  Esc quit", status)))?;
+
+    // Fourth line: how to fix the error, or the pinned terms
+    let pins = if let Some((_, Some(hint))) = error {
+      hint.to_string()
+    } else if pinned.is_empty() {
+      "^P pin".to_string()
+    } else {
+      format!("pinned: {}  ^P", pinned.join(", "))
+    };
+    self
+      .out
+      .queue(MoveTo(x, y + 3))?
+      .queue(Print(pins.dim()))?;
 
     self.out.flush()
   }
@@ -64,6 +111,9 @@ impl Screen {
 
 impl Drop for Screen {
   fn drop(&mut self) {
+    if self.headless {
+      return;
+    }
     let _ = self.out.execute(Show);
     let _ = terminal::disable_raw_mode();
   }
@@ -49,8 +49,8 @@ async fn test_concurrent_searches() -> Result<()> {
     
     // Wait for all searches to complete
     for handle in handles {
-        let result = handle.await??;
-        assert!(!result.is_empty() || true); // Some might not have matches
+        // Some might not have matches; we only care that every search succeeds
+        let _result = handle.await??;
     }
     
     Ok(())
@@ -0,0 +1,836 @@
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use kitty_fast_search::kitty::capabilities::Capabilities;
+use kitty_fast_search::kitty::commands::{Extent, KittyCommand, LaunchType};
+use kitty_fast_search::kitty::doctor::{self, Environment, Status};
+use kitty_fast_search::kitty::error::{hint_for, KittyError};
+use kitty_fast_search::kitty::fake::FakeKitty;
+use kitty_fast_search::kitty::marker::{MarkerSpec, PinnedTerm};
+use kitty_fast_search::kitty::response::Response;
+use kitty_fast_search::kitty::KittyClient;
+use kitty_fast_search::pins::PinStore;
+use kitty_fast_search::search::blocks::BlockFilter;
+use kitty_fast_search::search::SearchEngine;
+use kitty_fast_search::kitty::scroll::JumpPosition;
+use kitty_fast_search::ui::events::ScriptedEvents;
+use kitty_fast_search::ui::preview::{PreviewScroller, PREVIEW_INTERVAL};
+use kitty_fast_search::ui::SearchUI;
+use serde_json::json;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BUFFER: &str = "starting server\nERROR: disk full\nretrying\nERROR: disk still full\n";
+
+#[tokio::test]
+async fn test_search_and_jump_end_to_end() -> Result<()> {
+    let kitty = FakeKitty::new()
+        .with_text(BUFFER)
+        .with_windows(tab(vec![window(1, true, true)], vec![1], json!([])));
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, true, false)?;
+
+    let events = ScriptedEvents::new()
+        .text("ERROR")
+        .key(KeyCode::Down)
+        .key(KeyCode::Enter);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.run().await?;
+
+    // Each keystroke refreshes the marker for the current query, with the
+    // selected match in a group of its own
+    let markers = kitty.calls_to("create-marker");
+    assert!(!markers.is_empty());
+    assert_eq!(
+        markers.last().unwrap().args,
+        vec!["regex", "2", "ERROR(?=: disk still ful)", "1", "ERROR"]
+    );
+
+    // Enter jumps to the second match, then the marker is cleaned up. The
+    // whole buffer fits on screen, so that is just scrolling to the top.
+    let calls = kitty.calls();
+    let jump = calls.iter().rposition(|call| call.cmd == "scroll-window");
+    let cleanup = calls.iter().rposition(|call| call.cmd == "remove-marker");
+    assert_eq!(calls[jump.unwrap()].args, vec!["start"]);
+    assert!(cleanup.unwrap() > jump.unwrap());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_jump_centres_match_in_viewport() -> Result<()> {
+    // 100 lines, the match on line 61 wrapping over two rows below a
+    // wrapped line 11, in a 20 row window
+    let mut lines: Vec<String> = (1..=100).map(|i| format!("line {}", i)).collect();
+    lines[10] = "a long li\rne".to_string();
+    lines[60] = "needle th\rat wraps".to_string();
+    let wrapped = lines.join("\n") + "\n";
+    let plain = wrapped.replace('\r', "");
+
+    let mut window = window(1, true, true);
+    window["lines"] = json!(20);
+    let kitty = FakeKitty::new()
+        .with_text(&plain)
+        .with_text(&wrapped)
+        .with_windows(tab(vec![window], vec![1], json!([])));
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, true, false)?;
+
+    let events = ScriptedEvents::new().key(KeyCode::Enter);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_initial_query("needle".to_string());
+    ui.run().await?;
+
+    // The wrapped capture asks for wrap markers over the whole scrollback
+    let wrap_capture = kitty.calls_to("get-text").last().unwrap().to_rc_message();
+    assert_eq!(wrap_capture["payload"]["wrap_markers"], true);
+    assert_eq!(wrap_capture["payload"]["extent"], "all");
+
+    // Line 61 starts on row 61 (0-based, one extra row above it), so the
+    // top of the viewport goes 10 rows above that
+    let scrolls: Vec<_> = kitty
+        .calls_to("scroll-window")
+        .iter()
+        .map(|call| call.to_rc_message()["payload"]["amount"].clone())
+        .collect();
+    assert_eq!(scrolls, vec![json!(["start", null]), json!([51, "l"])]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_escape_exits_without_jumping() -> Result<()> {
+    let kitty = FakeKitty::new().with_text(BUFFER);
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, false, false)?;
+
+    let events = ScriptedEvents::new()
+        .text("disk")
+        .idle(Duration::from_millis(1))
+        .key(KeyCode::Esc)
+        .key(KeyCode::Esc);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.run().await?;
+
+    assert!(kitty.calls_to("scroll-window").is_empty());
+    assert!(!kitty.calls_to("get-text").is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_case_insensitive_regex_search_uses_iregex_marker() -> Result<()> {
+    let kitty = FakeKitty::new().with_text(BUFFER);
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, false, true)?;
+
+    let events = ScriptedEvents::new()
+        .idle(Duration::from_millis(1))
+        .key(KeyCode::Esc)
+        .key(KeyCode::Esc);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_initial_query("disk (still )?full".to_string());
+    ui.run().await?;
+
+    let markers = kitty.calls_to("create-marker");
+    assert_eq!(
+        markers[0].args,
+        vec!["iregex", "2", "(?<=ERROR: )disk full", "1", "disk (still )?full"]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scripted_get_text_responses() -> Result<()> {
+    let kitty = FakeKitty::new().with_text("first").with_text("second");
+    let client = KittyClient::with_transport(kitty.clone());
+
+    assert_eq!(client.get_buffer_content(None, Extent::All).await?, "first");
+    assert_eq!(client.get_buffer_content(None, Extent::All).await?, "second");
+    assert_eq!(client.get_buffer_content(None, Extent::All).await?, "second");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_kitty_errors_are_reported() -> Result<()> {
+    let kitty = FakeKitty::new().with_failure("get-text", "No matching windows");
+    let client = KittyClient::with_transport(kitty);
+
+    let err = client.get_buffer_content(None, Extent::All).await.unwrap_err();
+    assert!(err.to_string().contains("No matching windows"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_denied_command_names_missing_permission() -> Result<()> {
+    let kitty = FakeKitty::new().with_failure("create-marker", "Not authorized");
+    let client = KittyClient::with_transport(kitty);
+
+    let spec = MarkerSpec::for_search("ERROR", true, false);
+    let err = client.create_marker(None, &spec).await.unwrap_err();
+    assert!(err.to_string().contains("kitty denied `create-marker`"));
+    assert!(matches!(err.downcast_ref(), Some(KittyError::PermissionDenied { .. })));
+    assert!(hint_for(&err).unwrap().contains("remote_control_password \"<password>\" create-marker"));
+
+    Ok(())
+}
+
+/// Panel output shared with the test, as the UI owns its writer.
+#[derive(Clone, Default)]
+struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_ui_shows_kitty_errors_in_panel() -> Result<()> {
+    let kitty = FakeKitty::new()
+        .with_text(BUFFER)
+        .with_failure("create-marker", "Not authorized");
+    let client = KittyClient::with_transport(kitty);
+    let engine = SearchEngine::new(1000, true, false)?;
+    let output = SharedOutput::default();
+
+    let events = ScriptedEvents::new()
+        .text("ERROR")
+        .key(KeyCode::Enter)
+        .key(KeyCode::Esc)
+        .key(KeyCode::Esc);
+    let mut ui = SearchUI::headless(client, engine, events, output.clone());
+    ui.run().await?;
+
+    // The failed marker is reported, with its fix, and the UI keeps going
+    let panel = String::from_utf8_lossy(&output.0.lock().unwrap()).to_string();
+    assert!(panel.contains("kitty denied `create-marker`: Not authorized"));
+    assert!(panel.contains("remote_control_password \"<password>\" create-marker"));
+
+    Ok(())
+}
+
+fn window(id: u32, is_self: bool, is_focused: bool) -> serde_json::Value {
+    json!({
+        "id": id, "title": format!("window {}", id), "pid": 100 + id, "cwd": "/",
+        "cmdline": ["zsh"], "env": {}, "is_self": is_self, "is_focused": is_focused,
+        "lines": 24,
+    })
+}
+
+fn tab(windows: Vec<serde_json::Value>, history: Vec<u32>, groups: serde_json::Value) -> serde_json::Value {
+    json!([{
+        "id": 1, "is_focused": true,
+        "tabs": [{
+            "id": 1, "title": "tab", "layout": "splits",
+            "windows": windows, "active_window_history": history, "groups": groups,
+        }],
+    }])
+}
+
+#[tokio::test]
+async fn test_detects_window_under_overlay() -> Result<()> {
+    let windows = tab(
+        vec![window(1, false, false), window(2, false, false), window(3, true, true)],
+        vec![1, 2, 3],
+        json!([{"id": 1, "windows": [1]}, {"id": 2, "windows": [2, 3]}]),
+    );
+    let client = KittyClient::with_transport(FakeKitty::new().with_windows(windows));
+
+    assert_eq!(client.detect_target_window().await?, Some(2));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_detects_previously_active_split() -> Result<()> {
+    let windows = tab(
+        vec![window(1, false, false), window(2, false, false), window(3, true, true)],
+        vec![2, 1, 3],
+        json!([{"id": 1, "windows": [1]}, {"id": 2, "windows": [2]}, {"id": 3, "windows": [3]}]),
+    );
+    let client = KittyClient::with_transport(FakeKitty::new().with_windows(windows));
+
+    assert_eq!(client.detect_target_window().await?, Some(1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_detects_focused_window_from_outside_kitty() -> Result<()> {
+    let windows = tab(
+        vec![window(1, false, false), window(2, false, true)],
+        vec![1, 2],
+        json!([]),
+    );
+    let client = KittyClient::with_transport(FakeKitty::new().with_windows(windows));
+
+    assert_eq!(client.detect_target_window().await?, Some(2));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ui_targets_selected_window() -> Result<()> {
+    let kitty = FakeKitty::new()
+        .with_text(BUFFER)
+        .with_windows(tab(vec![window(7, false, true)], vec![7], json!([])));
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, true, false)?;
+
+    let events = ScriptedEvents::new().text("disk").key(KeyCode::Enter);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_target_window(Some(7));
+    ui.run().await?;
+
+    let calls = kitty.calls();
+    assert!(!calls.is_empty());
+    for call in calls.iter().filter(|call| call.cmd != "ls") {
+        assert_eq!(call.to_rc_message()["payload"]["match"], "id:7", "{:?}", call);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_launch_overlay_targets_current_window() -> Result<()> {
+    let kitty = FakeKitty::new();
+    let client = KittyClient::with_transport(kitty.clone());
+
+    let command = vec!["kitty-fast-search".to_string(), "--window-id".to_string(), "5".to_string()];
+    let env = [("KITTY_RC_PASSWORD", "hunter2")];
+    client.launch(LaunchType::Overlay, Some(5), &env, command).await?;
+
+    let launches = kitty.calls_to("launch");
+    assert_eq!(launches.len(), 1);
+    let message = launches[0].to_rc_message();
+    assert_eq!(message["payload"]["type"], "overlay");
+    assert_eq!(message["payload"]["next_to"], "id:5");
+    assert_eq!(message["payload"]["allow_remote_control"], true);
+    assert_eq!(message["payload"]["window_title"], "kitty-fast-search");
+    assert_eq!(
+        message["payload"]["args"],
+        json!(["kitty-fast-search", "--window-id", "5"])
+    );
+    // The password is passed in the environment, not on the command line
+    assert_eq!(message["payload"]["env"], json!(["KITTY_RC_PASSWORD=hunter2"]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_extent_toggle_refetches_text() -> Result<()> {
+    let kitty = FakeKitty::new().with_text(BUFFER);
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, true, false)?;
+
+    let ctrl_e = Event::Key(KeyEvent::new(KeyCode::Char('e'), KeyModifiers::CONTROL));
+    let events = ScriptedEvents::new()
+        .text("disk")
+        .idle(Duration::from_millis(1))
+        .event(ctrl_e)
+        .idle(Duration::from_millis(1))
+        .key(KeyCode::Esc)
+        .key(KeyCode::Esc);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_extent(Extent::LastCmdOutput);
+    ui.run().await?;
+
+    let extents: Vec<_> = kitty
+        .calls_to("get-text")
+        .iter()
+        .map(|call| call.to_rc_message()["payload"]["extent"].clone())
+        .collect();
+    assert_eq!(extents.first().unwrap(), "last_cmd_output");
+    assert_eq!(extents.last().unwrap(), "last_visited_cmd_output");
+
+    Ok(())
+}
+
+fn preview_kitty() -> FakeKitty {
+    let lines: Vec<String> = (1..=60)
+        .map(|i| if i % 20 == 0 { format!("ERROR {}", i) } else { format!("line {}", i) })
+        .collect();
+    FakeKitty::new()
+        .with_text(&(lines.join("\n") + "\n"))
+        .with_windows(tab(vec![window(1, true, true)], vec![1], json!([])))
+}
+
+fn scroll_amounts(kitty: &FakeKitty) -> Vec<serde_json::Value> {
+    kitty
+        .calls_to("scroll-window")
+        .iter()
+        .map(|call| call.to_rc_message()["payload"]["amount"].clone())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_navigation_previews_match_and_escape_restores() -> Result<()> {
+    let kitty = preview_kitty();
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, true, false)?;
+
+    let events = ScriptedEvents::new()
+        .key(KeyCode::Down)
+        .idle(Duration::from_millis(200))
+        .key(KeyCode::Esc)
+        .key(KeyCode::Esc);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_initial_query("ERROR".to_string());
+    ui.set_scroll_origin(5);
+    ui.run().await?;
+
+    // Line 40 centred in a 24 row window, then back to 5 lines above the end
+    assert_eq!(
+        scroll_amounts(&kitty),
+        vec![json!(["start", null]), json!([27, "l"]), json!(["end", null]), json!([-5, "l"])]
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_held_arrow_key_only_scrolls_to_latest_match() -> Result<()> {
+    let kitty = preview_kitty();
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, true, false)?;
+
+    let events = ScriptedEvents::new()
+        .key(KeyCode::Down)
+        .key(KeyCode::Down)
+        .key(KeyCode::Up)
+        .key(KeyCode::Down)
+        .key(KeyCode::Enter);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_initial_query("ERROR".to_string());
+    ui.run().await?;
+
+    // Previews superseded before their slot are dropped, and Enter jumps to
+    // line 60 without a restore
+    let amounts = scroll_amounts(&kitty);
+    assert!(amounts.len() < 8, "{:?}", amounts);
+    assert_eq!(amounts[amounts.len() - 2..], [json!(["start", null]), json!([36, "l"])]);
+    assert!(!amounts.contains(&json!(["end", null])));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_held_arrow_key_does_not_fall_behind() -> Result<()> {
+    let kitty = preview_kitty();
+    let client = KittyClient::with_transport(kitty.clone());
+    let mut scroller = PreviewScroller::new(client, Some(1), 0);
+
+    // A dozen presses faster than the preview interval, ending on line 60
+    let started = Instant::now();
+    for i in 0..12 {
+        let line_number = if i == 11 { 60 } else { 20 + 20 * (i % 2) };
+        let line = format!("ERROR {}\n", line_number);
+        scroller.preview(Extent::All, 60, 0, line_number, line, JumpPosition::Center);
+    }
+    while scroll_amounts(&kitty).last() != Some(&json!([36, "l"])) {
+        assert!(started.elapsed() < Duration::from_secs(2), "{:?}", scroll_amounts(&kitty));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    // The last preview waits one interval after the first, not one per press
+    let elapsed = started.elapsed();
+    assert!(elapsed < PREVIEW_INTERVAL * 4, "{:?}", elapsed);
+    scroller.cancel().await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pinned_terms_stay_highlighted_after_exit() -> Result<()> {
+    let kitty = FakeKitty::new().with_text(BUFFER);
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, false, false)?;
+
+    let ctrl_p = Event::Key(KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL));
+    let events = ScriptedEvents::new()
+        .event(ctrl_p.clone())
+        .key(KeyCode::Esc)
+        .text("retry")
+        .event(ctrl_p)
+        .key(KeyCode::Esc)
+        .key(KeyCode::Esc);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_initial_query("disk".to_string());
+    ui.set_keep_markers(true);
+    ui.run().await?;
+
+    // Both pins get their own group, and are all that is left on exit
+    let markers = kitty.calls_to("create-marker");
+    assert_eq!(markers.last().unwrap().args, vec!["iregex", "1", "disk", "2", "retry"]);
+    assert!(kitty.calls_to("remove-marker").is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pinning_again_unpins() -> Result<()> {
+    let kitty = FakeKitty::new().with_text(BUFFER);
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, false, false)?;
+
+    let ctrl_p = Event::Key(KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL));
+    let events = ScriptedEvents::new()
+        .event(ctrl_p.clone())
+        .event(ctrl_p)
+        .key(KeyCode::Esc)
+        .key(KeyCode::Esc);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_initial_query("disk".to_string());
+    ui.set_keep_markers(true);
+    ui.run().await?;
+
+    let calls = kitty.calls();
+    assert_eq!(calls.last().unwrap().cmd, "remove-marker");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_kept_pins_do_not_keep_other_windows_highlighted() -> Result<()> {
+    let windows = json!([{
+        "id": 1, "is_focused": true,
+        "tabs": [
+            {"id": 1, "title": "build", "layout": "splits", "windows": [window(1, true, true)]},
+            {"id": 2, "title": "server", "layout": "splits", "windows": [window(3, false, false)]},
+        ],
+    }]);
+    let kitty = FakeKitty::new()
+        .with_windows(windows)
+        .with_window_text(1, "search overlay\n")
+        .with_window_text(3, "thread 'main' panicked at src/main.rs\n");
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, false, false)?;
+
+    let ctrl_p = Event::Key(KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL));
+    let events = ScriptedEvents::new()
+        .idle(Duration::from_millis(1))
+        .event(ctrl_p)
+        .key(KeyCode::Enter);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_initial_query("panicked".to_string());
+    ui.set_all_windows(true);
+    ui.set_keep_markers(true);
+    ui.run().await?;
+
+    // The window of the last match was highlighted, and is cleared on exit
+    let calls = kitty.calls();
+    let marked = calls
+        .iter()
+        .rposition(|call| call.cmd == "create-marker" && call.to_rc_message()["payload"]["match"] == "id:3")
+        .unwrap();
+    let cleared = calls
+        .iter()
+        .rposition(|call| call.cmd == "remove-marker" && call.to_rc_message()["payload"]["match"] == "id:3");
+    assert!(cleared.is_some_and(|cleared| cleared > marked), "{:?}", calls);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_all_windows_search_focuses_matching_window() -> Result<()> {
+    let windows = json!([{
+        "id": 1, "is_focused": true,
+        "tabs": [
+            {"id": 1, "title": "build", "layout": "splits", "windows": [window(1, false, false), window(2, true, true)]},
+            {"id": 2, "title": "server", "layout": "splits", "windows": [window(3, false, false)]},
+        ],
+    }]);
+    let kitty = FakeKitty::new()
+        .with_windows(windows)
+        .with_window_text(1, "compiling\nfinished\n")
+        .with_window_text(2, "panic: in the search overlay itself\n")
+        .with_window_text(3, "listening\nthread 'main' panicked at src/main.rs\n");
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, false, false)?;
+
+    let events = ScriptedEvents::new().key(KeyCode::Enter);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_initial_query("panic".to_string());
+    ui.set_all_windows(true);
+    ui.run().await?;
+
+    // Our own window is not searched
+    let searched: Vec<_> = kitty
+        .calls_to("get-text")
+        .iter()
+        .map(|call| call.to_rc_message()["payload"]["match"].clone())
+        .collect();
+    assert!(!searched.contains(&json!("id:2")));
+
+    // The match is highlighted in its window, which is focused and then
+    // scrolled
+    let calls = kitty.calls();
+    let marker = calls.iter().position(|call| call.cmd == "create-marker").unwrap();
+    assert_eq!(calls[marker].to_rc_message()["payload"]["match"], "id:3");
+    let focus = calls.iter().position(|call| call.cmd == "focus-window").unwrap();
+    let scroll = calls.iter().position(|call| call.cmd == "scroll-window").unwrap();
+    assert_eq!(calls[focus].to_rc_message()["payload"]["match"], "id:3");
+    assert_eq!(calls[scroll].to_rc_message()["payload"]["match"], "id:3");
+    assert!(focus < scroll);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_execute_returns_typed_responses() -> Result<()> {
+    let kitty = FakeKitty::new()
+        .with_text("some text")
+        .with_windows(tab(vec![window(1, true, true)], vec![1], json!([])));
+    let client = KittyClient::with_transport(kitty.clone());
+
+    let text = client.execute(KittyCommand::get_text(Extent::Screen)).await?;
+    assert_eq!(text, Response::Text("some text".to_string()));
+
+    let windows = client.execute(KittyCommand::list_windows()).await?.into_windows()?;
+    assert_eq!(windows.self_window().map(|w| w.id), Some(1));
+
+    let done = client.execute(KittyCommand::set_window_title("logs").for_window(Some(1))).await?;
+    assert_eq!(done, Response::Done);
+    assert_eq!(kitty.calls_to("set-window-title")[0].to_rc_message()["payload"]["title"], "logs");
+
+    Ok(())
+}
+
+fn kitty_environment() -> Environment {
+    Environment {
+        kitty_version: Some("kitty 0.35.2 created by Kovid Goyal".to_string()),
+        window_id: Some("1".to_string()),
+        listen_on: None,
+    }
+}
+
+#[tokio::test]
+async fn test_doctor_passes_on_working_setup() -> Result<()> {
+    let mut shell = window(1, true, true);
+    shell["last_reported_cmdline"] = json!("kitty-fast-search doctor");
+    let kitty = FakeKitty::new()
+        .with_text(BUFFER)
+        .with_windows(tab(vec![shell], vec![1], json!([])));
+    let client = Ok(KittyClient::with_transport(kitty.clone()));
+
+    let report = doctor::diagnose(&kitty_environment(), &client, &PinStore::default(), false).await;
+    assert!(report.passed(), "{}", report);
+    assert!(report.checks.iter().all(|check| check.status == Status::Pass), "{}", report);
+
+    // The marker probe cleans up after itself, and nothing else is changed
+    let calls: Vec<String> = kitty.calls().into_iter().map(|call| call.cmd).collect();
+    assert_eq!(calls, ["ls", "get-text", "create-marker", "remove-marker"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_doctor_keeps_pinned_highlights() -> Result<()> {
+    let kitty = FakeKitty::new()
+        .with_text(BUFFER)
+        .with_windows(tab(vec![window(1, true, true)], vec![1], json!([])));
+    let client = Ok(KittyClient::with_transport(kitty.clone()));
+    let mut pins = PinStore::default().with_instance(Some("pid:1".to_string()));
+    let pin = PinnedTerm {
+        group: 1,
+        query: "ERROR".to_string(),
+        case_sensitive: false,
+        regex: false,
+    };
+    pins.set_pins(Some(1), vec![pin]);
+
+    // Removing a probe marker would clear the pins kept with --keep-markers
+    let report = doctor::diagnose(&kitty_environment(), &client, &pins, false).await;
+    assert_eq!(report.check("markers").unwrap().status, Status::Pass);
+    assert!(kitty.calls_to("create-marker").is_empty());
+    assert!(kitty.calls_to("remove-marker").is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_doctor_probes_launch_on_request() -> Result<()> {
+    let kitty = FakeKitty::new()
+        .with_text(BUFFER)
+        .with_windows(tab(vec![window(1, true, true)], vec![1], json!([])))
+        .with_failure("launch", "Not authorized");
+    let client = Ok(KittyClient::with_transport(kitty.clone()));
+
+    let report = doctor::diagnose(&kitty_environment(), &client, &PinStore::default(), true).await;
+    assert_eq!(report.check("scroll-window").unwrap().status, Status::Pass);
+    assert_eq!(report.check("focus-window").unwrap().status, Status::Pass);
+    assert_eq!(report.check("launch").unwrap().status, Status::Fail);
+
+    // Scrolls by nothing and focuses the window that already has focus
+    assert_eq!(kitty.calls_to("scroll-window")[0].args, vec!["--match=id:1", "0"]);
+    assert_eq!(kitty.calls_to("focus-window")[0].args, vec!["--match=id:1"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_doctor_reports_fixes() -> Result<()> {
+    let kitty = FakeKitty::new()
+        .with_windows(tab(vec![window(1, true, true)], vec![1], json!([])))
+        .with_failure("get-text", "Not authorized")
+        .with_failure("create-marker", "Unknown remote control command: create-marker");
+    let client = Ok(KittyClient::with_transport(kitty));
+
+    let report = doctor::diagnose(&kitty_environment(), &client, &PinStore::default(), false).await;
+    assert!(!report.passed());
+
+    let get_text = report.check("get-text").unwrap();
+    assert_eq!(get_text.status, Status::Fail);
+    assert!(get_text.fix.as_deref().unwrap().contains("remote_control_password"));
+    assert_eq!(report.check("markers").unwrap().status, Status::Fail);
+    assert_eq!(report.check("shell integration").unwrap().status, Status::Warn);
+    assert_eq!(report.check("ls").unwrap().status, Status::Pass);
+
+    let printed = report.to_string();
+    assert!(printed.contains("[fail] get-text"));
+    assert!(printed.contains("fix: update kitty"));
+    assert!(printed.ends_with("4 passed, 1 warnings, 2 failed"));
+    assert!(report.check("launch").is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_old_kitty_degrades_instead_of_failing() -> Result<()> {
+    let kitty = FakeKitty::new().with_text(BUFFER);
+    let capabilities = Capabilities::for_version(Some([0, 14, 0]));
+    let client = KittyClient::with_transport(kitty.clone()).with_capabilities(capabilities);
+    let engine = SearchEngine::new(1000, true, false)?;
+    let output = SharedOutput::default();
+
+    let events = ScriptedEvents::new().text("ERROR").key(KeyCode::Enter);
+    let mut ui = SearchUI::headless(client, engine, events, output.clone());
+    ui.set_extent(Extent::LastCmdOutput);
+    ui.run().await?;
+
+    // Searches everything instead, without highlighting or scrolling
+    let panel = String::from_utf8_lossy(&output.0.lock().unwrap()).to_string();
+    assert!(panel.contains("kitty 0.14.0 lacks the last_cmd_output extent, highlighting, jumping"));
+    let capture = kitty.calls_to("get-text")[0].to_rc_message();
+    assert_eq!(capture["payload"]["extent"], "all");
+    let calls: Vec<String> = kitty.calls().into_iter().map(|call| call.cmd).collect();
+    assert!(calls.iter().all(|cmd| cmd == "get-text"), "{:?}", calls);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_jump_without_wrap_markers() -> Result<()> {
+    let kitty = FakeKitty::new()
+        .with_text(BUFFER)
+        .with_windows(tab(vec![window(1, true, true)], vec![1], json!([])));
+    let capabilities = Capabilities::for_version(Some([0, 19, 0]));
+    let client = KittyClient::with_transport(kitty.clone()).with_capabilities(capabilities);
+    let engine = SearchEngine::new(1000, true, false)?;
+
+    let events = ScriptedEvents::new().key(KeyCode::Enter);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.set_initial_query("retrying".to_string());
+    ui.run().await?;
+
+    let capture = kitty.calls_to("get-text").last().unwrap().to_rc_message();
+    assert!(capture["payload"].get("wrap_markers").is_none());
+    assert_eq!(kitty.calls_to("scroll-window").len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_typing_ahead_searches_once() -> Result<()> {
+    let kitty = FakeKitty::new().with_text(BUFFER);
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, true, false)?;
+
+    let events = ScriptedEvents::new()
+        .text("disk")
+        .idle(Duration::from_millis(1))
+        .key(KeyCode::Esc)
+        .key(KeyCode::Esc);
+    let mut ui = SearchUI::headless(client, engine, events, std::io::sink());
+    ui.run().await?;
+
+    // Only the query the user paused at is searched and highlighted
+    assert_eq!(kitty.calls_to("get-text").len(), 1);
+    let markers = kitty.calls_to("create-marker");
+    assert_eq!(markers.len(), 1);
+    assert_eq!(markers[0].args.last().unwrap(), "disk");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reads_are_retried_after_timeout() -> Result<()> {
+    let kitty = FakeKitty::new().with_text("hello").with_stall("get-text", 1);
+    let client = KittyClient::with_transport(kitty.clone()).with_timeout(Duration::from_millis(20));
+
+    assert_eq!(client.get_buffer_content(None, Extent::All).await?, "hello");
+    assert_eq!(kitty.calls_to("get-text").len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_commands_time_out_without_retry() -> Result<()> {
+    let kitty = FakeKitty::new().with_stall("create-marker", 1);
+    let client = KittyClient::with_transport(kitty.clone()).with_timeout(Duration::from_millis(20));
+
+    let spec = MarkerSpec::for_search("ERROR", true, false);
+    let err = client.create_marker(None, &spec).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(KittyError::Timeout { .. })));
+    assert_eq!(err.to_string(), "kitty did not answer `create-marker` within 20ms");
+    assert_eq!(kitty.calls_to("create-marker").len(), 1);
+
+    // Reads give up too once their attempts are used up
+    let kitty = FakeKitty::new().with_stall("ls", 5);
+    let client = KittyClient::with_transport(kitty.clone()).with_timeout(Duration::from_millis(20));
+    assert!(client.list_windows().await.is_err());
+    assert_eq!(kitty.calls_to("ls").len(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_outputs_filter_uses_kitty_exit_status() -> Result<()> {
+    // kitty keeps prompt and output marks in `get-text --ansi`, but the exit
+    // status only comes from `ls`
+    let capture = "\x1b]133;A\x1b\\$ make\n\x1b]133;C\x1b\\test parse ... ok\n\
+                   \x1b]133;A\x1b\\$ make test\n\x1b]133;C\x1b\\test app ... FAILED\n\
+                   \x1b]133;A\x1b\\$ \n";
+    let mut shell = window(1, true, true);
+    shell["at_prompt"] = json!(true);
+    shell["last_cmd_exit_status"] = json!(2);
+    let kitty = FakeKitty::new()
+        .with_text(capture)
+        .with_windows(tab(vec![shell], vec![1], json!([])));
+    let client = KittyClient::with_transport(kitty.clone());
+    let engine = SearchEngine::new(1000, true, false)?;
+    let output = SharedOutput::default();
+
+    let events = ScriptedEvents::new()
+        .idle(Duration::from_millis(1))
+        .key(KeyCode::Esc)
+        .key(KeyCode::Esc);
+    let mut ui = SearchUI::headless(client, engine, events, output.clone());
+    ui.set_block_filter(Some(BlockFilter::Failed));
+    ui.set_initial_query("test".to_string());
+    ui.run().await?;
+
+    // Only the failing test line matches, labelled with its command
+    let panel = String::from_utf8_lossy(&output.0.lock().unwrap()).to_string();
+    assert!(panel.contains("in all, `$ make test` (exit 2)"), "{}", panel);
+    assert!(panel.contains("1/1"));
+    let capture = kitty.calls_to("get-text")[0].to_rc_message();
+    assert_eq!(capture["payload"]["ansi"], true);
+
+    Ok(())
+}